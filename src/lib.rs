@@ -1,2 +1,13 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(any(feature = "std", feature = "libm")))]
+compile_error!("either the `std` or the `libm` feature must be enabled for the f32 math");
+
+extern crate alloc;
+
+// Re-exported so callers can name the map types used in the public API.
+pub use hashbrown;
+
 pub mod quadtree;
 pub mod rect;
+pub mod util;
@@ -1,12 +1,31 @@
-use std::{collections::HashMap, fmt::Debug};
-
-use crate::rect::Rect;
-
+use alloc::{boxed::Box, collections::BinaryHeap, format, string::String, vec, vec::Vec};
+use core::{
+    cmp::Reverse,
+    fmt::{self, Debug},
+    iter, mem,
+};
+
+use hashbrown::{HashMap, HashSet};
+
+use crate::{
+    rect::{Rect, RectKey},
+    util::morton_encode,
+};
+
+/// `Quadtree<T>` is `Send` and `Sync` whenever `T` is. Queries only need `&self`,
+/// so several threads can read the same tree (e.g. behind an `RwLock`) while a
+/// writer uses `&mut self` methods.
 pub struct Quadtree<T> {
     max_node_capacity: usize,
     root: Node,
     elements: HashMap<u64, (T, Rect)>,
     next_id: u64,
+    element_limit: Option<usize>,
+    // Ids in eviction order when there is an element limit. Removed ids are
+    // dropped lazily, when they reach the top or the heap is rebuilt.
+    eviction_order: BinaryHeap<Reverse<u64>>,
+    fuse_threshold: Option<usize>,
+    max_depth: Option<u32>,
 }
 
 pub struct NodeIter<'a> {
@@ -32,6 +51,60 @@ pub struct EntryMut<'a, T> {
     owner: &'a mut Quadtree<T>,
 }
 
+/// Elements that know their own region, see [`Quadtree::insert_bounded`].
+pub trait Bounded {
+    fn bounds(&self) -> Rect;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QuadtreeError {
+    OutOfBounds(Rect),
+    InvalidRegion(Rect),
+}
+
+/// Elements per leaf, see [`Quadtree::capacity_report`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CapacityReport {
+    pub leaf_count: usize,
+    pub min_elements_per_leaf: usize,
+    pub max_elements_per_leaf: usize,
+    pub avg_elements_per_leaf: f32,
+    /// Nodes directly holding more than `max_node_capacity` elements. Leaves
+    /// subdivide before overflowing, so these are nodes with many straddling
+    /// elements or leaves at the maximum depth.
+    pub overfull_nodes: usize,
+}
+
+/// Nodes and the elements they directly hold at one depth, see
+/// [`Quadtree::stats_per_depth`].
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct DepthStats {
+    pub node_count: usize,
+    pub element_count: usize,
+}
+
+type QueryFilter<'a, T> = Box<dyn Fn(&T) -> bool + 'a>;
+
+pub struct QueryBuilder<'a, T> {
+    owner: &'a Quadtree<T>,
+    region: Option<Rect>,
+    filter: Option<QueryFilter<'a, T>>,
+    limit: Option<usize>,
+}
+
+/// Configures every tree parameter at once, see [`Quadtree::new`] and the
+/// other constructors for what each one does. Unset parameters match
+/// [`Quadtree::default`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuadtreeBuilder {
+    region: Rect,
+    max_node_capacity: usize,
+    max_depth: Option<u32>,
+    fuse_threshold: Option<usize>,
+    element_limit: Option<usize>,
+    capacity: usize,
+}
+
 impl<'a, T> Entry<'a, T> {
     pub fn value(&self) -> &T {
         &self.owner.elements[&self.id].0
@@ -59,10 +132,111 @@ impl<'a, T: Debug> EntryMut<'a, T> {
         self.id
     }
 
+    /// # Panics
+    ///
+    /// Panics if `new_region` is not contained in the root region, see [`EntryMut::try_move_entry`].
     pub fn move_entry(&mut self, new_region: Rect) {
         self.owner
             .move_element(self.id, self.owner.elements[&self.id].1, new_region);
     }
+
+    /// Moves the entry by a delta from its current region.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the moved region is not contained in the root region.
+    pub fn move_by(&mut self, dx: f32, dy: f32) {
+        let new_region = self.region().translate(dx, dy);
+        self.move_entry(new_region);
+    }
+
+    pub fn remove(self) -> (T, Rect) {
+        self.owner.remove(self.id).unwrap()
+    }
+
+    pub fn try_move_entry(&mut self, new_region: Rect) -> Result<(), QuadtreeError> {
+        self.owner.check_region(new_region)?;
+
+        self.move_entry(new_region);
+
+        Ok(())
+    }
+}
+
+impl<'a, T: Debug + Bounded> EntryMut<'a, T> {
+    /// Mutates the value with `f`, then moves the entry to the value's new bounds.
+    pub fn update_bounded(&mut self, f: impl FnOnce(&mut T)) {
+        let element = &mut self.owner.elements.get_mut(&self.id).unwrap().0;
+        f(element);
+
+        let new_region = element.bounds();
+        self.move_entry(new_region);
+    }
+}
+
+impl fmt::Display for QuadtreeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QuadtreeError::OutOfBounds(region) => {
+                write!(f, "region {:?} is not contained in the root region", region)
+            }
+            QuadtreeError::InvalidRegion(region) => {
+                write!(
+                    f,
+                    "region {:?} has non-finite coordinates or a negative size",
+                    region
+                )
+            }
+        }
+    }
+}
+
+impl core::error::Error for QuadtreeError {}
+
+impl<'a, T> QueryBuilder<'a, T> {
+    pub fn region(mut self, region: Rect) -> Self {
+        self.region = Some(region);
+        self
+    }
+
+    pub fn filter(mut self, predicate: impl Fn(&T) -> bool + 'a) -> Self {
+        self.filter = Some(Box::new(predicate));
+        self
+    }
+
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn collect(self) -> Vec<&'a T> {
+        let mut result = Vec::new();
+        self.for_each(|element| result.push(element));
+
+        result
+    }
+
+    /// Visits the elements overlapping the query region (or every element if no
+    /// region was set) that pass the filter, stopping once the limit is reached.
+    pub fn for_each(self, mut f: impl FnMut(&'a T)) {
+        let owner = self.owner;
+        let region = self.region.unwrap_or(owner.root.region);
+        let mut remaining = self.limit.unwrap_or(usize::MAX);
+
+        if remaining == 0 {
+            return;
+        }
+
+        owner.root.visit_overlapped(region, &mut |id| {
+            let element = &owner.elements[&id].0;
+            if self.filter.as_ref().is_none_or(|filter| filter(element)) {
+                f(element);
+                remaining -= 1;
+            }
+
+            remaining > 0
+        });
+    }
 }
 
 impl Node {
@@ -90,6 +264,12 @@ impl Node {
         self.size
     }
 
+    /// The four children in top left, top right, bottom left, bottom right
+    /// order, `None` for a leaf.
+    pub fn children(&self) -> Option<&[Node; 4]> {
+        self.children.as_deref()
+    }
+
     fn new(region: Rect) -> Self {
         Self {
             region,
@@ -100,10 +280,10 @@ impl Node {
         }
     }
 
-    fn insert(&mut self, id: u64, region: Rect, max_node_capacity: usize) {
+    fn insert(&mut self, id: u64, region: Rect, max_node_capacity: usize, max_depth: u32) {
         assert!(
             self.region.contains(&region),
-            "Trying to insert element with id {} and region {:?} whitch is not contained in nodes region: {:?}",
+            "Trying to insert element with id {} and region {:?} which is not contained in nodes region: {:?}",
             id,
             region,
             self.region
@@ -111,55 +291,42 @@ impl Node {
 
         self.size += 1;
 
-        if self.is_leaf() && self.elements.len() < max_node_capacity {
+        // Leaves at `max_depth` take any number of elements instead of subdividing.
+        if self.is_leaf() && (self.elements.len() < max_node_capacity || self.depth >= max_depth) {
             self.elements.insert(id, region);
             return;
         }
 
         if self.is_leaf() && self.elements.len() == max_node_capacity {
-            self.subdivide(max_node_capacity);
+            self.subdivide(max_node_capacity, max_depth);
         }
 
         for child in self.children.as_mut().unwrap().iter_mut() {
             if child.region.contains(&region) {
-                child.insert(id, region, max_node_capacity);
+                child.insert(id, region, max_node_capacity, max_depth);
                 return;
             }
         }
 
+        // No single child contains the region (it straddles a split line), so the
+        // element stays in the lowest node enclosing it, even if that node is not a leaf.
         self.elements.insert(id, region);
     }
 
-    fn subdivide(&mut self, max_node_capacity: usize) {
-        let children_w = self.region.w / 2.0;
-        let children_h = self.region.h / 2.0;
-
-        #[rustfmt::skip]
-        let mut children = [
-            // Top left
-            Node::new(Rect::new(self.region.x, self.region.y, children_w, children_h)),
-
-            // Top right
-            Node::new(Rect::new(self.region.x + children_w, self.region.y, children_w, children_h)),
-            
-            // Bottom left
-            Node::new(Rect::new(self.region.x, self.region.y + children_h, children_w, children_h)),
-            
-            // Bottom right
-            Node::new(Rect::new(self.region.x + children_w, self.region.y + children_h, children_w, children_h)),
-        ];
+    fn subdivide(&mut self, max_node_capacity: usize, max_depth: u32) {
+        let mut children = self.region.quadrants().map(Node::new);
 
         for child in children.iter_mut() {
             child.depth = self.depth + 1;
         }
 
-        let old_elements = std::mem::take(&mut self.elements);
+        let old_elements = mem::take(&mut self.elements);
 
         for (id, region) in old_elements {
             let mut inserted = false;
             for child in children.iter_mut() {
                 if child.region.contains(&region) {
-                    child.insert(id, region, max_node_capacity);
+                    child.insert(id, region, max_node_capacity, max_depth);
                     inserted = true;
                     break;
                 }
@@ -202,7 +369,7 @@ impl Node {
             for child in children.as_ref() {
                 if region.contains(&child.region) {
                     result.extend(child.get_all());
-                } else if region.overlapps(&child.region) {
+                } else if region.overlaps(&child.region) {
                     result.extend(child.get_contained(region));
                 }
             }
@@ -215,7 +382,7 @@ impl Node {
         let mut result = Vec::new();
 
         for (id, element_region) in self.elements.iter() {
-            if region.overlapps(&element_region) {
+            if region.overlaps(element_region) {
                 result.push(*id);
             }
         }
@@ -224,7 +391,7 @@ impl Node {
             for child in children.as_ref() {
                 if region.contains(&child.region) {
                     result.extend(child.get_all());
-                } else if region.overlapps(&child.region) {
+                } else if region.overlaps(&child.region) {
                     result.extend(child.get_overlapped(region));
                 }
             }
@@ -233,25 +400,348 @@ impl Node {
         result
     }
 
-    fn remove(&mut self, id: u64, region: Rect, max_node_capacity: usize) {
+    fn get_overlapped_nodes<'a>(
+        &'a self,
+        region: Rect,
+        max_depth: u32,
+        result: &mut Vec<&'a Node>,
+    ) {
+        match &self.children {
+            Some(children) if self.depth < max_depth => {
+                for child in children.as_ref() {
+                    if region.overlaps(&child.region) {
+                        child.get_overlapped_nodes(region, max_depth, result);
+                    }
+                }
+            }
+            _ => result.push(self),
+        }
+    }
+
+    fn get_at_point(&self, x: f32, y: f32, result: &mut Vec<u64>) {
+        for (id, element_region) in self.elements.iter() {
+            if element_region.contains_point(x, y) {
+                result.push(*id);
+            }
+        }
+
+        if let Some(children) = &self.children {
+            for child in children.as_ref() {
+                if child.region.contains_point(x, y) {
+                    child.get_at_point(x, y, result);
+                }
+            }
+        }
+    }
+
+    fn get_intersecting(&self, intersects: &impl Fn(&Rect) -> bool, result: &mut Vec<u64>) {
+        for (id, element_region) in self.elements.iter() {
+            if intersects(element_region) {
+                result.push(*id);
+            }
+        }
+
+        if let Some(children) = &self.children {
+            for child in children.as_ref() {
+                if intersects(&child.region) {
+                    child.get_intersecting(intersects, result);
+                }
+            }
+        }
+    }
+
+    fn visit_overlapped<F>(&self, region: Rect, f: &mut F) -> bool
+    where
+        F: FnMut(u64) -> bool,
+    {
+        for (id, element_region) in self.elements.iter() {
+            if region.overlaps(element_region) && !f(*id) {
+                return false;
+            }
+        }
+
+        if let Some(children) = &self.children {
+            for child in children.as_ref() {
+                if region.overlaps(&child.region) && !child.visit_overlapped(region, f) {
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+
+    fn raycast(&self, origin: (f32, f32), dir: (f32, f32), result: &mut Vec<(u64, f32)>) {
+        for (id, element_region) in self.elements.iter() {
+            if let Some(t) = element_region.ray_intersection(origin, dir) {
+                result.push((*id, t));
+            }
+        }
+
+        if let Some(children) = &self.children {
+            for child in children.as_ref() {
+                if child.region.ray_intersection(origin, dir).is_some() {
+                    child.raycast(origin, dir, result);
+                }
+            }
+        }
+    }
+
+    fn raycast_first(&self, origin: (f32, f32), dir: (f32, f32), best: &mut Option<(u64, f32)>) {
+        for (id, element_region) in self.elements.iter() {
+            let Some(t) = element_region.ray_intersection(origin, dir) else {
+                continue;
+            };
+
+            let is_better = match best {
+                Some((best_id, best_t)) => t < *best_t || (t == *best_t && id < best_id),
+                None => true,
+            };
+            if is_better {
+                *best = Some((*id, t));
+            }
+        }
+
+        if let Some(children) = &self.children {
+            let mut children: Vec<(&Node, f32)> = children
+                .iter()
+                .filter_map(|child| Some((child, child.region.ray_intersection(origin, dir)?)))
+                .collect();
+            children.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+            for (child, t) in children {
+                if let Some((_, best_t)) = best {
+                    if t > *best_t {
+                        break;
+                    }
+                }
+
+                child.raycast_first(origin, dir, best);
+            }
+        }
+    }
+
+    // `distance` must never be larger for a node region than for a rect it
+    // contains, since node distances are used to skip whole subtrees.
+    fn nearest<F>(&self, distance: &F, best: &mut Option<(u64, f32)>)
+    where
+        F: Fn(&Rect) -> f32,
+    {
+        for (id, element_region) in self.elements.iter() {
+            let distance = distance(element_region);
+            let is_better = match best {
+                Some((best_id, best_distance)) => {
+                    distance < *best_distance || (distance == *best_distance && id < best_id)
+                }
+                None => true,
+            };
+
+            if is_better {
+                *best = Some((*id, distance));
+            }
+        }
+
+        if let Some(children) = &self.children {
+            let mut children: Vec<(&Node, f32)> = children
+                .iter()
+                .map(|child| (child, distance(&child.region)))
+                .collect();
+            children.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+            for (child, child_distance) in children {
+                if let Some((_, best_distance)) = best {
+                    if child_distance > *best_distance {
+                        break;
+                    }
+                }
+
+                child.nearest(distance, best);
+            }
+        }
+    }
+
+    /// Closest partner of the element `id` at `region`, compared against the
+    /// best pair found so far rather than a per-element bound, so once a close
+    /// pair is known every later descent stops near its own element.
+    fn closest_pair(&self, id: u64, region: &Rect, best: &mut Option<(u64, u64, f32)>) {
+        for (other, other_region) in self.elements.iter() {
+            if *other == id {
+                continue;
+            }
+
+            let distance = region.distance_to_rect(other_region);
+            let pair = (id.min(*other), id.max(*other));
+            let is_better = match best {
+                Some((a, b, best_distance)) => {
+                    distance < *best_distance || (distance == *best_distance && pair < (*a, *b))
+                }
+                None => true,
+            };
+
+            if is_better {
+                *best = Some((pair.0, pair.1, distance));
+            }
+        }
+
+        if let Some(children) = &self.children {
+            let mut children: Vec<(&Node, f32)> = children
+                .iter()
+                .map(|child| (child, region.distance_to_rect(&child.region)))
+                .collect();
+            children.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+            for (child, child_distance) in children {
+                if let Some((_, _, best_distance)) = best {
+                    if child_distance > *best_distance {
+                        break;
+                    }
+                }
+
+                child.closest_pair(id, region, best);
+            }
+        }
+    }
+
+    fn remove(&mut self, id: u64, region: Rect, fuse_threshold: usize) {
+        self.size -= 1;
+
+        if let Some(children) = &mut self.children {
+            for child in children.as_mut() {
+                if child.region.contains(&region) {
+                    child.remove(id, region, fuse_threshold);
+                    break;
+                }
+            }
+        }
+
+        self.elements.remove(&id);
+
+        if self.can_fuse(fuse_threshold) {
+            self.fuse();
+        }
+    }
+
+    // Like `remove`, but leaves fusing to a later `fuse_along`.
+    fn remove_unfused(&mut self, id: u64, region: Rect) {
         self.size -= 1;
 
         if let Some(children) = &mut self.children {
             for child in children.as_mut() {
                 if child.region.contains(&region) {
-                    child.remove(id, region, max_node_capacity);
+                    child.remove_unfused(id, region);
                     break;
                 }
             }
         }
 
         self.elements.remove(&id);
+    }
+
+    /// Fuses bottom-up along the paths `remove_unfused` took for `regions`,
+    /// the only nodes whose size changed, and returns how many were visited.
+    fn fuse_along(&mut self, regions: &[Rect], fuse_threshold: usize) -> usize {
+        let mut visited = 1;
+
+        if let Some(children) = &mut self.children {
+            let mut child_regions: [Vec<Rect>; 4] = Default::default();
+            for region in regions {
+                if let Some(i) = children
+                    .iter()
+                    .position(|child| child.region.contains(region))
+                {
+                    child_regions[i].push(*region);
+                }
+            }
+
+            for (child, regions) in children.iter_mut().zip(&child_regions) {
+                if !regions.is_empty() {
+                    visited += child.fuse_along(regions, fuse_threshold);
+                }
+            }
+        }
+
+        if self.can_fuse(fuse_threshold) {
+            self.fuse();
+        }
+
+        visited
+    }
+
+    fn remove_contained(&mut self, region: Rect, fuse_threshold: usize, removed: &mut Vec<u64>) {
+        let before = removed.len();
+
+        self.elements.retain(|id, element_region| {
+            if region.contains(element_region) {
+                removed.push(*id);
+                return false;
+            }
+
+            true
+        });
+
+        if let Some(children) = &mut self.children {
+            for child in children.as_mut() {
+                if region.overlaps(&child.region) {
+                    child.remove_contained(region, fuse_threshold, removed);
+                }
+            }
+        }
+
+        self.size -= removed.len() - before;
 
-        if self.size == max_node_capacity {
+        if self.can_fuse(fuse_threshold) {
             self.fuse();
         }
     }
 
+    fn pre_subdivide(&mut self, region: Rect, depth: u32, max_node_capacity: usize) {
+        if self.depth >= depth {
+            return;
+        }
+
+        if self.is_leaf() {
+            self.subdivide(max_node_capacity, depth);
+        }
+
+        for child in self.children.as_mut().unwrap().iter_mut() {
+            if region.overlaps(&child.region) {
+                child.pre_subdivide(region, depth, max_node_capacity);
+            }
+        }
+    }
+
+    fn prune_empty(&mut self) {
+        if self.size == 0 {
+            self.children = None;
+            return;
+        }
+
+        if let Some(children) = &mut self.children {
+            for child in children.as_mut() {
+                child.prune_empty();
+            }
+        }
+    }
+
+    fn shrink_to_fit(&mut self) {
+        self.elements.shrink_to_fit();
+
+        if let Some(children) = &mut self.children {
+            for child in children.as_mut() {
+                child.shrink_to_fit();
+            }
+        }
+    }
+
+    fn can_fuse(&self, fuse_threshold: usize) -> bool {
+        match &self.children {
+            Some(children) => {
+                self.size <= fuse_threshold && children.iter().all(|child| child.is_leaf())
+            }
+            None => false,
+        }
+    }
+
     fn fuse(&mut self) {
         debug_assert!(self.is_node());
         let mut children_elements = HashMap::new();
@@ -273,352 +763,3802 @@ impl Node {
         old_region: Rect,
         new_region: Rect,
         max_node_capacity: usize,
+        max_depth: u32,
+        fuse_threshold: usize,
     ) {
         if let Some(children) = &mut self.children {
             for child in children.as_mut() {
                 if child.region.contains(&old_region) && child.region.contains(&new_region) {
-                    child.move_element(id, old_region, new_region, max_node_capacity);
+                    child.move_element(
+                        id,
+                        old_region,
+                        new_region,
+                        max_node_capacity,
+                        max_depth,
+                        fuse_threshold,
+                    );
                     return;
                 }
 
                 if child.region.contains(&old_region) {
-                    child.remove(id, old_region, max_node_capacity);
+                    child.remove(id, old_region, fuse_threshold);
                     self.size -= 1;
-                    self.insert(id, new_region, max_node_capacity);
+                    self.insert(id, new_region, max_node_capacity, max_depth);
                     return;
                 }
             }
         }
 
+        // The element is held here. When it would land here again, e.g. small
+        // moves inside a leaf, update its region in place.
+        let stays = match &self.children {
+            Some(children) => !children
+                .iter()
+                .any(|child| child.region.contains(&new_region)),
+            None => true,
+        };
+        if stays && self.region.contains(&new_region) {
+            self.elements.insert(id, new_region);
+            return;
+        }
+
         self.elements.remove(&id);
         self.size -= 1;
-        self.insert(id, new_region, max_node_capacity);
+        self.insert(id, new_region, max_node_capacity, max_depth);
     }
 }
 
-impl<T> Quadtree<T> {
-    pub fn new(region: Rect, max_node_capacity: usize) -> Self {
-        let root = Node::new(region);
-        Self {
-            max_node_capacity,
-            root,
-            elements: HashMap::new(),
-            next_id: 0,
-        }
+impl QuadtreeBuilder {
+    pub fn new() -> Self {
+        Self::default()
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.elements.is_empty()
+    pub fn region(mut self, region: Rect) -> Self {
+        self.region = region;
+        self
     }
 
-    pub fn size(&self) -> usize {
-        self.elements.len()
-    }
+    /// # Panics
+    ///
+    /// Panics if `max_node_capacity` is `0`.
+    pub fn max_node_capacity(mut self, max_node_capacity: usize) -> Self {
+        assert!(
+            max_node_capacity > 0,
+            "Node capacity must be greater than 0"
+        );
 
-    pub fn insert(&mut self, element: T, region: Rect) -> u64 {
-        let id = self.next_id;
-        self.elements.insert(id, (element, region));
+        self.max_node_capacity = max_node_capacity;
+        self
+    }
 
-        self.root.insert(id, region, self.max_node_capacity);
+    pub fn max_depth(mut self, max_depth: u32) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
 
-        self.next_id += 1;
+    pub fn fuse_threshold(mut self, fuse_threshold: usize) -> Self {
+        self.fuse_threshold = Some(fuse_threshold);
+        self
+    }
 
-        id
+    pub fn element_limit(mut self, limit: usize) -> Self {
+        self.element_limit = Some(limit);
+        self
     }
 
-    pub fn get_contained(&self, region: Rect) -> Vec<&T> {
-        let ids = self.root.get_contained(region);
-        ids.into_iter().map(|id| &self.elements[&id].0).collect()
+    /// Number of elements to allocate room for upfront.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
     }
 
-    pub fn get_contained_mut(&mut self, region: Rect) -> Vec<&mut T> {
-        let ids = self.root.get_contained(region);
-        let mut result = Vec::new();
-        unsafe {
-            for id in ids {
-                let map_ptr = &mut self.elements as *mut HashMap<u64, (T, Rect)>;
-                result.push(&mut map_ptr.as_mut().unwrap().get_mut(&id).unwrap().0);
-            }
+    /// # Panics
+    ///
+    /// Panics if the element limit is `0` or the fuse threshold is greater
+    /// than the node capacity, like [`Quadtree::with_element_limit`] and
+    /// [`Quadtree::with_fuse_threshold`].
+    pub fn build<T>(self) -> Quadtree<T> {
+        if let Some(limit) = self.element_limit {
+            assert!(limit > 0, "Element limit must be greater than 0");
+        }
+        if let Some(fuse_threshold) = self.fuse_threshold {
+            assert!(
+                fuse_threshold <= self.max_node_capacity,
+                "Fuse threshold must not be greater than the node capacity"
+            );
         }
 
-        result
+        let elements = HashMap::with_capacity(self.capacity);
+
+        Quadtree {
+            elements,
+            element_limit: self.element_limit,
+            fuse_threshold: self.fuse_threshold,
+            max_depth: self.max_depth,
+            ..Quadtree::new(self.region, self.max_node_capacity)
+        }
+    }
+}
+
+impl Default for QuadtreeBuilder {
+    fn default() -> Self {
+        Self {
+            region: Rect::new(-100.0, -100.0, 200.0, 200.0),
+            max_node_capacity: 5,
+            max_depth: None,
+            fuse_threshold: None,
+            element_limit: None,
+            capacity: 0,
+        }
+    }
+}
+
+impl<T> Quadtree<T> {
+    /// # Panics
+    ///
+    /// Panics if `max_node_capacity` is `0`, since a node could never hold
+    /// an element without subdividing.
+    pub fn new(region: Rect, max_node_capacity: usize) -> Self {
+        assert!(
+            max_node_capacity > 0,
+            "Node capacity must be greater than 0"
+        );
+
+        let root = Node::new(region);
+        Self {
+            max_node_capacity,
+            root,
+            elements: HashMap::new(),
+            next_id: 0,
+            element_limit: None,
+            eviction_order: BinaryHeap::new(),
+            fuse_threshold: None,
+            max_depth: None,
+        }
+    }
+
+    /// Creates a tree with a square root region, so every node stays square.
+    /// Nodes inherit the root's aspect ratio, and with elongated nodes the
+    /// distance from a point to a node's region is a looser bound on the
+    /// distance to its elements, so nearest queries prune fewer nodes.
+    pub fn new_square(center: (f32, f32), half_size: f32, max_node_capacity: usize) -> Self {
+        let size = half_size * 2.0;
+        Self::new(
+            Rect::new_centered(center.0, center.1, size, size),
+            max_node_capacity,
+        )
+    }
+
+    /// Creates a tree holding at most `limit` elements. Once full, every new
+    /// insertion first evicts the element with the lowest id (FIFO for ids
+    /// produced by [`Quadtree::insert`]).
+    pub fn with_element_limit(region: Rect, max_node_capacity: usize, limit: usize) -> Self {
+        assert!(limit > 0, "Element limit must be greater than 0");
+
+        Self {
+            element_limit: Some(limit),
+            ..Self::new(region, max_node_capacity)
+        }
+    }
+
+    /// Creates a tree whose nodes fuse back only once they hold at most
+    /// `fuse_threshold` elements. Nodes still subdivide past
+    /// `max_node_capacity`, so a lower threshold keeps an element oscillating
+    /// across the capacity from subdividing and fusing a node every time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `fuse_threshold` is greater than `max_node_capacity`.
+    pub fn with_fuse_threshold(
+        region: Rect,
+        max_node_capacity: usize,
+        fuse_threshold: usize,
+    ) -> Self {
+        assert!(
+            fuse_threshold <= max_node_capacity,
+            "Fuse threshold must not be greater than the node capacity"
+        );
+
+        Self {
+            fuse_threshold: Some(fuse_threshold),
+            ..Self::new(region, max_node_capacity)
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+
+    pub fn size(&self) -> usize {
+        self.elements.len()
+    }
+
+    /// Number of elements the element map can hold without reallocating. It
+    /// does not account for the nodes, see [`Quadtree::memory_usage`].
+    pub fn capacity(&self) -> usize {
+        self.elements.capacity()
+    }
+
+    pub fn max_node_capacity(&self) -> usize {
+        self.max_node_capacity
+    }
+
+    /// Size at or below which a node fuses its children back. Defaults to the
+    /// node capacity and never exceeds it.
+    pub fn fuse_threshold(&self) -> usize {
+        self.fuse_threshold
+            .unwrap_or(self.max_node_capacity)
+            .min(self.max_node_capacity)
+    }
+
+    /// Depth at which nodes stop subdividing, `None` when unlimited. Leaves at
+    /// this depth can hold more than `max_node_capacity` elements.
+    pub fn max_depth(&self) -> Option<u32> {
+        self.max_depth
+    }
+
+    fn depth_limit(&self) -> u32 {
+        self.max_depth.unwrap_or(u32::MAX)
+    }
+
+    /// Changes the node capacity and rebuilds the node tree to match it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_node_capacity` is `0`, like [`Quadtree::new`].
+    pub fn set_max_node_capacity(&mut self, max_node_capacity: usize) {
+        assert!(
+            max_node_capacity > 0,
+            "Node capacity must be greater than 0"
+        );
+
+        self.max_node_capacity = max_node_capacity;
+        self.rebalance();
+    }
+
+    /// Depth of the deepest node, `0` when the root is a leaf.
+    pub fn height(&self) -> u32 {
+        self.nodes().map(|node| node.depth).max().unwrap_or(0)
+    }
+
+    /// Smallest rect containing every element, `None` when empty.
+    pub fn bounding_box(&self) -> Option<Rect> {
+        self.elements
+            .values()
+            .map(|(_, region)| *region)
+            .reduce(|a, b| a.union(&b))
+    }
+
+    /// Mean of the element region centers, `None` when empty.
+    pub fn centroid(&self) -> Option<(f32, f32)> {
+        self.weighted_centroid_by(|_| 1.0)
+    }
+
+    /// Mean of the element region centers weighted by their area, `None` when
+    /// empty or when every region has zero area.
+    pub fn weighted_centroid(&self) -> Option<(f32, f32)> {
+        self.weighted_centroid_by(Rect::area)
+    }
+
+    fn weighted_centroid_by(&self, weight: impl Fn(&Rect) -> f32) -> Option<(f32, f32)> {
+        let (total, x, y) =
+            self.elements
+                .values()
+                .fold((0.0, 0.0, 0.0), |(total, x, y), (_, region)| {
+                    let weight = weight(region);
+                    let (cx, cy) = region.center();
+                    (total + weight, x + cx * weight, y + cy * weight)
+                });
+
+        if total <= 0.0 {
+            return None;
+        }
+
+        Some((x / total, y / total))
+    }
+
+    pub fn min_id(&self) -> Option<u64> {
+        self.elements.keys().min().copied()
+    }
+
+    pub fn max_id(&self) -> Option<u64> {
+        self.elements.keys().max().copied()
+    }
+
+    pub fn insert(&mut self, element: T, region: Rect) -> u64 {
+        self.insert_evicting(element, region).0
+    }
+
+    /// Inserts like [`Quadtree::insert`] and also returns the element evicted
+    /// to respect the element limit, if any.
+    ///
+    /// # Panics
+    ///
+    /// Panics, before changing the tree, once every id below `u64::MAX` has
+    /// been handed out (see [`Quadtree::insert_with_id`]).
+    pub fn insert_evicting(&mut self, element: T, region: Rect) -> (u64, Option<(u64, T, Rect)>) {
+        assert_valid_region(region);
+        let id = self.next_id;
+        let next_id = id
+            .checked_add(1)
+            .expect("Element id must be less than u64::MAX");
+
+        let evicted = self.evict_for_insert();
+
+        self.elements.insert(id, (element, region));
+        self.track_for_eviction(id);
+
+        self.root
+            .insert(id, region, self.max_node_capacity, self.depth_limit());
+
+        self.next_id = next_id;
+
+        (id, evicted)
+    }
+
+    /// Inserts like [`Quadtree::insert`] but returns an error instead of
+    /// panicking when the region is invalid or outside the root region.
+    pub fn try_insert(&mut self, element: T, region: Rect) -> Result<u64, QuadtreeError> {
+        self.check_region(region)?;
+
+        Ok(self.insert(element, region))
+    }
+
+    pub fn insert_many(&mut self, items: impl IntoIterator<Item = (T, Rect)>) -> Vec<u64> {
+        let items = items.into_iter();
+        let (lower, _) = items.size_hint();
+        self.elements.reserve(lower);
+
+        let mut ids = Vec::with_capacity(lower);
+        for (element, region) in items {
+            ids.push(self.insert(element, region));
+        }
+
+        ids
+    }
+
+    pub fn insert_bounded(&mut self, element: T) -> u64
+    where
+        T: Bounded,
+    {
+        let region = element.bounds();
+        self.insert(element, region)
+    }
+
+    /// Inserts the element under `id`, returning the element it displaced.
+    /// Later calls to [`Quadtree::insert`] hand out ids above `id`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id` is `u64::MAX`, since no id would be left above it.
+    pub fn insert_with_id(&mut self, id: u64, element: T, region: Rect) -> Option<T> {
+        self.insert_with_id_evicting(id, element, region).0
+    }
+
+    /// Inserts like [`Quadtree::insert_with_id`] and also returns the element
+    /// evicted to respect the element limit, if any.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id` is `u64::MAX`, like [`Quadtree::insert_with_id`].
+    pub fn insert_with_id_evicting(
+        &mut self,
+        id: u64,
+        element: T,
+        region: Rect,
+    ) -> (Option<T>, Option<(u64, T, Rect)>) {
+        assert_valid_region(region);
+        let next_id = id
+            .checked_add(1)
+            .expect("Element id must be less than u64::MAX");
+
+        let displaced = self.remove(id).map(|(element, _)| element);
+        let evicted = self.evict_for_insert();
+
+        self.elements.insert(id, (element, region));
+        self.track_for_eviction(id);
+
+        self.root
+            .insert(id, region, self.max_node_capacity, self.depth_limit());
+
+        self.next_id = self.next_id.max(next_id);
+
+        (displaced, evicted)
+    }
+
+    /// Id of an element whose region is bit-equal to `region` (see [`RectKey`]),
+    /// otherwise inserts the element built by `f` and returns the new id.
+    /// Panics like [`Quadtree::insert`] when inserting.
+    pub fn get_or_insert_with(&mut self, region: Rect, f: impl FnOnce() -> T) -> u64 {
+        let key = RectKey(region);
+        let existing = self
+            .contained_ids(region)
+            .into_iter()
+            .find(|id| RectKey(self.elements[id].1) == key);
+
+        match existing {
+            Some(id) => id,
+            None => self.insert(f(), region),
+        }
+    }
+
+    /// Elements whose region is contained in `region`, in no particular order.
+    /// The query is clamped to the root region first, so the part outside the
+    /// root is never searched.
+    pub fn get_contained(&self, region: Rect) -> Vec<&T> {
+        let ids = self.contained_ids(region);
+        ids.into_iter().map(|id| &self.elements[&id].0).collect()
+    }
+
+    pub fn get_contained_mut(&mut self, region: Rect) -> Vec<&mut T> {
+        let ids = self.contained_ids(region);
+        self.elements_mut(ids)
     }
 
+    /// Elements whose region overlaps `region`, in no particular order. The
+    /// query is clamped to the root region first, so the part outside the root
+    /// is never searched.
     pub fn get_overlapped(&self, region: Rect) -> Vec<&T> {
-        let ids = self.root.get_overlapped(region);
+        let ids = self.overlapped_ids(region);
         ids.into_iter().map(|id| &self.elements[&id].0).collect()
     }
 
-    pub fn get_overlapped_mut(&mut self, region: Rect) -> Vec<&mut T> {
-        let ids = self.root.get_overlapped(region);
-        let mut result = Vec::new();
-        unsafe {
-            for id in ids {
-                let map_ptr = &mut self.elements as *mut HashMap<u64, (T, Rect)>;
-                result.push(&mut map_ptr.as_mut().unwrap().get_mut(&id).unwrap().0);
+    /// Same as [`Quadtree::get_contained`] but clears and fills `out` instead of
+    /// allocating, so a scratch buffer can be reused across queries.
+    pub fn get_contained_into<'a>(&'a self, region: Rect, out: &mut Vec<&'a T>) {
+        out.clear();
+        self.root.visit_overlapped(region, &mut |id| {
+            let (element, element_region) = &self.elements[&id];
+            if region.contains(element_region) {
+                out.push(element);
             }
-        }
 
-        result
+            true
+        });
     }
 
-    pub fn entry<'a>(&'a mut self, id: u64) -> Entry<'a, T> {
-        debug_assert!(self.elements.get(&id).is_some());
+    /// Same as [`Quadtree::get_overlapped`] but clears and fills `out` instead
+    /// of allocating, so a scratch buffer can be reused across queries.
+    pub fn get_overlapped_into<'a>(&'a self, region: Rect, out: &mut Vec<&'a T>) {
+        out.clear();
+        self.root.visit_overlapped(region, &mut |id| {
+            out.push(&self.elements[&id].0);
+            true
+        });
+    }
 
-        Entry { id, owner: self }
+    /// Elements overlapping `region` split in one traversal into those it
+    /// contains and those only partially inside it, in no particular order.
+    pub fn partition_by_region(&self, region: Rect) -> (Vec<&T>, Vec<&T>) {
+        let mut contained = Vec::new();
+        let mut partial = Vec::new();
+        self.root.visit_overlapped(region, &mut |id| {
+            let (element, element_region) = &self.elements[&id];
+            if region.contains(element_region) {
+                contained.push(element);
+            } else {
+                partial.push(element);
+            }
+
+            true
+        });
+
+        (contained, partial)
     }
 
-    pub fn entry_mut<'a>(&'a mut self, id: u64) -> EntryMut<'a, T> {
-        debug_assert!(self.elements.get(&id).is_some());
+    /// Same as [`Quadtree::get_contained`] but ordered by id, for stable output.
+    pub fn get_contained_ordered(&self, region: Rect) -> Vec<&T> {
+        let mut ids = self.contained_ids(region);
+        ids.sort_unstable();
+        ids.into_iter().map(|id| &self.elements[&id].0).collect()
+    }
 
-        EntryMut { id, owner: self }
+    /// Same as [`Quadtree::get_overlapped`] but ordered by id, for stable output.
+    pub fn get_overlapped_ordered(&self, region: Rect) -> Vec<&T> {
+        let mut ids = self.overlapped_ids(region);
+        ids.sort_unstable();
+        ids.into_iter().map(|id| &self.elements[&id].0).collect()
     }
 
-    pub fn remove(&mut self, id: u64) -> Option<(T, Rect)> {
-        let element = self.elements.remove(&id);
+    pub fn get_overlapped_mut(&mut self, region: Rect) -> Vec<&mut T> {
+        let ids = self.overlapped_ids(region);
+        self.elements_mut(ids)
+    }
 
-        if let Some((element, region)) = element {
-            self.root.remove(id, region, self.max_node_capacity);
-            Some((element, region))
-        } else {
-            None
+    /// Elements whose region overlaps the convex polygon `vertices`. Polygons
+    /// with fewer than 3 vertices match nothing.
+    pub fn get_in_polygon(&self, vertices: &[(f32, f32)]) -> Vec<&T> {
+        let mut ids = Vec::new();
+        if self.root.region.intersects_polygon(vertices) {
+            self.root.get_intersecting(
+                &|region: &Rect| region.intersects_polygon(vertices),
+                &mut ids,
+            );
         }
+
+        ids.into_iter().map(|id| &self.elements[&id].0).collect()
     }
 
-    pub fn entries<'a>(&'a self) -> impl Iterator<Item = Entry<'a, T>> {
-        let iter = self.elements.keys().map(|id| Entry {
-            id: *id,
-            owner: self,
-        });
+    /// Elements overlapping the quad `corners`, e.g. a projected view frustum.
+    /// The corners go around the quad in order, and the quad must be convex;
+    /// concave or self-intersecting quads are not supported.
+    pub fn get_in_quad(&self, corners: [(f32, f32); 4]) -> Vec<&T> {
+        self.get_in_polygon(&corners)
+    }
 
-        iter
+    /// Elements overlapping `region` except `exclude`, e.g. the element the
+    /// query is made around. In no particular order.
+    pub fn get_overlapped_excluding(&self, region: Rect, exclude: u64) -> Vec<(u64, &T)> {
+        self.overlapped_where(region, |id| id != exclude)
+    }
+
+    /// Elements overlapping `region` whose id is not in `exclude`.
+    pub fn get_overlapped_excluding_set(
+        &self,
+        region: Rect,
+        exclude: &HashSet<u64>,
+    ) -> Vec<(u64, &T)> {
+        self.overlapped_where(region, |id| !exclude.contains(&id))
+    }
+
+    /// Elements overlapping `region`, sorted by the distance from their region
+    /// to `from`, ties broken by id.
+    pub fn get_overlapped_sorted(&self, region: Rect, from: (f32, f32)) -> Vec<(u64, &T)> {
+        let mut ids: Vec<(f32, u64)> = self
+            .overlapped_ids(region)
+            .into_iter()
+            .map(|id| (self.elements[&id].1.distance_to_point(from.0, from.1), id))
+            .collect();
+        ids.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+
+        ids.into_iter()
+            .map(|(_, id)| (id, &self.elements[&id].0))
+            .collect()
     }
 
-    pub fn entries_mut<'a>(&'a mut self) -> impl Iterator<Item = EntryMut<'a, T>> {
-        unsafe {
-            let self_ptr = self as *mut Self;
-            self.elements.keys().map(move |id| EntryMut {
-                id: *id,
-                owner: &mut *self_ptr,
+    /// Elements overlapping `region` with their overlap area against it, largest
+    /// area first, ties broken by id.
+    pub fn get_overlapped_ranked(&self, region: Rect) -> Vec<(u64, &T, f32)> {
+        let mut ranked: Vec<(u64, &T, f32)> = self
+            .overlapped_ids(region)
+            .into_iter()
+            .map(|id| {
+                let (element, element_region) = &self.elements[&id];
+                (id, element, region.overlap_area(element_region))
             })
-        }
+            .collect();
+        ranked.sort_by(|a, b| b.2.total_cmp(&a.2).then(a.0.cmp(&b.0)));
+
+        ranked
     }
 
-    pub fn nodes<'a>(&'a self) -> NodeIter<'a> {
-        NodeIter {
-            nodes_to_process: vec![&self.root],
+    /// Element overlapping `region` with the smallest `y`, ties broken by id.
+    pub fn topmost_in_region(&self, region: Rect) -> Option<(u64, &T)> {
+        self.extremum_in_region(region, |region| region.y)
+    }
+
+    /// Element overlapping `region` with the smallest `x`, ties broken by id.
+    pub fn leftmost_in_region(&self, region: Rect) -> Option<(u64, &T)> {
+        self.extremum_in_region(region, |region| region.x)
+    }
+
+    fn extremum_in_region(&self, region: Rect, key: impl Fn(&Rect) -> f32) -> Option<(u64, &T)> {
+        self.overlapped_ids(region)
+            .into_iter()
+            .map(|id| (key(&self.elements[&id].1), id))
+            .min_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)))
+            .map(|(_, id)| (id, &self.elements[&id].0))
+    }
+
+    /// Exact area of `region` covered by at least one element, so overlapping
+    /// elements are only counted once. Sweeps the distinct x edges of the
+    /// clipped elements and merges their y spans in each slab.
+    pub fn coverage(&self, region: Rect) -> f32 {
+        let clipped: Vec<Rect> = self
+            .overlapped_ids(region)
+            .into_iter()
+            .filter_map(|id| self.elements[&id].1.intersection(&region))
+            .filter(|clipped| clipped.area() > 0.0)
+            .collect();
+
+        let mut xs: Vec<f32> = clipped.iter().flat_map(|r| [r.x, r.x + r.w]).collect();
+        xs.sort_by(f32::total_cmp);
+        xs.dedup();
+
+        let mut area = 0.0;
+        let mut spans = Vec::new();
+        for slab in xs.windows(2) {
+            spans.clear();
+            spans.extend(
+                clipped
+                    .iter()
+                    .filter(|r| r.x <= slab[0] && r.x + r.w >= slab[1])
+                    .map(|r| (r.y, r.y + r.h)),
+            );
+            spans.sort_by(|a: &(f32, f32), b| a.0.total_cmp(&b.0));
+
+            let mut covered = 0.0;
+            let mut current: Option<(f32, f32)> = None;
+            for &(start, end) in spans.iter() {
+                current = match current {
+                    Some((s, e)) if start <= e => Some((s, e.max(end))),
+                    Some((s, e)) => {
+                        covered += e - s;
+                        Some((start, end))
+                    }
+                    None => Some((start, end)),
+                };
+            }
+            if let Some((s, e)) = current {
+                covered += e - s;
+            }
+
+            area += covered * (slab[1] - slab[0]);
         }
+
+        area
     }
 
-    fn move_element(&mut self, id: u64, old_region: Rect, new_region: Rect) {
-        self.root
-            .move_element(id, old_region, new_region, self.max_node_capacity);
+    pub fn any_overlapped(&self, region: Rect) -> bool {
+        !self.root.visit_overlapped(region, &mut |_| false)
+    }
 
-        self.elements.get_mut(&id).unwrap().1 = new_region;
+    /// First overlapping element for which `f` returns `true`, the traversal
+    /// stops as soon as it is found.
+    pub fn find_overlapped<F>(&self, region: Rect, mut f: F) -> Option<(u64, &T)>
+    where
+        F: FnMut(u64, &T) -> bool,
+    {
+        let mut found = None;
+        self.root.visit_overlapped(region, &mut |id| {
+            let element = &self.elements[&id].0;
+            if f(id, element) {
+                found = Some((id, element));
+                return false;
+            }
+
+            true
+        });
+
+        found
     }
-}
 
-impl<T> Quadtree<T>
-where
-    T: PartialEq,
-{
-    pub fn contains(&self, element: &T) -> bool {
-        self.elements.values().any(|(e, _)| e == element)
+    /// Nearest hit of [`Quadtree::raycast`], skipping nodes the ray enters
+    /// after a hit that was already found.
+    pub fn raycast_first(&self, origin: (f32, f32), dir: (f32, f32)) -> Option<(u64, &T, f32)> {
+        let mut best = None;
+        if self.root.region.ray_intersection(origin, dir).is_some() {
+            self.root.raycast_first(origin, dir, &mut best);
+        }
+
+        best.map(|(id, t)| (id, &self.elements[&id].0, t))
     }
-}
 
-impl<T> Default for Quadtree<T> {
-    fn default() -> Self {
-        Self {
-            max_node_capacity: 5,
-            root: Node::new(Rect::new(-100.0, -100.0, 200.0, 200.0)),
-            elements: HashMap::new(),
-            next_id: 0,
+    /// Elements hit by the ray `origin + t * dir`, sorted front-to-back by the
+    /// parametric distance `t` of their first intersection, then by id.
+    pub fn raycast(&self, origin: (f32, f32), dir: (f32, f32)) -> Vec<(u64, &T, f32)> {
+        let mut hits = Vec::new();
+        if self.root.region.ray_intersection(origin, dir).is_some() {
+            self.root.raycast(origin, dir, &mut hits);
         }
+
+        hits.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+
+        hits.into_iter()
+            .map(|(id, t)| (id, &self.elements[&id].0, t))
+            .collect()
     }
-}
 
-impl<'a> Iterator for NodeIter<'a> {
-    type Item = &'a Node;
+    /// Nodes overlapping `region` that are either leaves or at `max_depth`,
+    /// without descending below `max_depth`. Together they partition the
+    /// overlapped part of the tree, so each can stand in for its subtree.
+    pub fn get_overlapped_nodes(&self, region: Rect, max_depth: u32) -> Vec<&Node> {
+        let mut result = Vec::new();
+        if region.overlaps(&self.root.region) {
+            self.root
+                .get_overlapped_nodes(region, max_depth, &mut result);
+        }
+
+        result
+    }
+
+    /// Elements overlapping `region` grouped by the node holding them, with that
+    /// node's region. Besides leaves, this includes inner nodes holding
+    /// straddling elements. Nodes without matching elements are left out.
+    pub fn overlapped_grouped(&self, region: Rect) -> Vec<(Rect, Vec<&T>)> {
+        let mut groups = Vec::new();
+        let mut nodes = vec![&self.root];
+        while let Some(node) = nodes.pop() {
+            if !region.overlaps(&node.region) {
+                continue;
+            }
+
+            let elements: Vec<&T> = node
+                .elements
+                .iter()
+                .filter(|(_, element_region)| region.overlaps(element_region))
+                .map(|(id, _)| &self.elements[id].0)
+                .collect();
+            if !elements.is_empty() {
+                groups.push((node.region, elements));
+            }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if let Some(node) = self.nodes_to_process.pop() {
             if let Some(children) = &node.children {
-                for child in children.as_ref() {
-                    self.nodes_to_process.push(child);
-                }
+                nodes.extend(children.iter());
             }
+        }
 
-            return Some(node);
+        groups
+    }
+
+    /// Region and subtree size of the nodes from [`Quadtree::get_overlapped_nodes`],
+    /// for deciding whether a finer query is worth running. Elements held by
+    /// the shallower nodes these descend from are not counted.
+    pub fn coarse_overlapped(&self, region: Rect, depth: u32) -> Vec<(Rect, usize)> {
+        self.get_overlapped_nodes(region, depth)
+            .into_iter()
+            .map(|node| (node.region, node.size))
+            .collect()
+    }
+
+    pub fn query(&self) -> QueryBuilder<'_, T> {
+        QueryBuilder {
+            owner: self,
+            region: None,
+            filter: None,
+            limit: None,
         }
+    }
 
-        None
+    pub fn nearest_to_rect(&self, query: Rect) -> Option<(u64, &T)> {
+        let mut best = None;
+        self.root
+            .nearest(&|region| query.distance_to_rect(region), &mut best);
+
+        best.map(|(id, _)| (id, &self.elements[&id].0))
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::rect::Rect;
+    /// Element closest to `point` under a custom distance, e.g. Manhattan or
+    /// with a weighted axis. Ties are broken by id. Node regions are measured
+    /// with `dist` too, to skip subtrees, so `dist` must never be larger for a
+    /// rect than for a rect it contains; any distance to the closest point of
+    /// the rect under a norm satisfies this.
+    pub fn nearest_by<F>(&self, point: (f32, f32), dist: F) -> Option<(u64, &T)>
+    where
+        F: Fn(&Rect, (f32, f32)) -> f32,
+    {
+        let mut best = None;
+        self.root.nearest(&|region| dist(region, point), &mut best);
+
+        best.map(|(id, _)| (id, &self.elements[&id].0))
+    }
+
+    /// The two elements whose regions are closest, with their distance (`0`
+    /// when they overlap). Ties are broken by the smallest pair of ids.
+    pub fn closest_pair(&self) -> Option<(u64, u64, f32)> {
+        let mut best = None;
+        for (id, (_, region)) in self.elements.iter() {
+            self.root.closest_pair(*id, region, &mut best);
+        }
+
+        best
+    }
+
+    pub fn entry<'a>(&'a mut self, id: u64) -> Entry<'a, T> {
+        debug_assert!(self.elements.contains_key(&id));
+
+        Entry { id, owner: self }
+    }
+
+    pub fn entry_mut<'a>(&'a mut self, id: u64) -> EntryMut<'a, T> {
+        debug_assert!(self.elements.contains_key(&id));
+
+        EntryMut { id, owner: self }
+    }
+
+    /// Mutable handle to the element under `point`. When several regions contain
+    /// the point, the one with the smallest area wins, then the lowest id.
+    pub fn entry_mut_at_point(&mut self, point: (f32, f32)) -> Option<EntryMut<'_, T>> {
+        let mut ids = Vec::new();
+        self.root.get_at_point(point.0, point.1, &mut ids);
+
+        let id = ids
+            .into_iter()
+            .map(|id| (self.elements[&id].1.area(), id))
+            .min_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)))?
+            .1;
+
+        Some(EntryMut { id, owner: self })
+    }
+
+    pub fn remove(&mut self, id: u64) -> Option<(T, Rect)> {
+        let element = self.elements.remove(&id);
+
+        if let Some((element, region)) = element {
+            self.root.remove(id, region, self.fuse_threshold());
+            Some((element, region))
+        } else {
+            None
+        }
+    }
+
+    /// Removes elements whose region is within `epsilon` of an element with a
+    /// lower id on all four fields, so the lowest id of each group survives.
+    /// Returns how many were removed.
+    pub fn deduplicate(&mut self, epsilon: f32) -> usize {
+        let mut ids: Vec<u64> = self.elements.keys().copied().collect();
+        ids.sort();
+
+        let is_close = |a: &Rect, b: &Rect| {
+            (a.x - b.x).abs() <= epsilon
+                && (a.y - b.y).abs() <= epsilon
+                && (a.w - b.w).abs() <= epsilon
+                && (a.h - b.h).abs() <= epsilon
+        };
+
+        let mut kept = HashSet::new();
+        let mut duplicates = Vec::new();
+        for id in ids {
+            let region = self.elements[&id].1;
+            let is_duplicate = self
+                .overlapped_ids(region.inset(-epsilon, -epsilon, -epsilon, -epsilon))
+                .into_iter()
+                .any(|other| kept.contains(&other) && is_close(&region, &self.elements[&other].1));
+
+            if is_duplicate {
+                duplicates.push(id);
+            } else {
+                kept.insert(id);
+            }
+        }
+
+        self.remove_many(&duplicates).len()
+    }
+
+    /// Removes all `ids`, then fuses nodes in one bottom-up pass over the
+    /// paths the removals went through, instead of after every removal like
+    /// [`Quadtree::remove`]. The removed elements come back in the order of
+    /// `ids`, unknown ids are skipped.
+    pub fn remove_many(&mut self, ids: &[u64]) -> Vec<(T, Rect)> {
+        let mut removed = Vec::with_capacity(ids.len());
+        for id in ids {
+            if let Some((element, region)) = self.elements.remove(id) {
+                self.root.remove_unfused(*id, region);
+                removed.push((element, region));
+            }
+        }
+
+        let regions: Vec<Rect> = removed.iter().map(|(_, region)| *region).collect();
+        self.root.fuse_along(&regions, self.fuse_threshold());
+
+        removed
+    }
+
+    pub fn remove_in_region(&mut self, region: Rect) -> Vec<(u64, T, Rect)> {
+        let mut ids = Vec::new();
+        self.root
+            .remove_contained(region, self.fuse_threshold(), &mut ids);
+        ids.sort();
+
+        ids.into_iter()
+            .map(|id| {
+                let (element, region) = self.elements.remove(&id).unwrap();
+                (id, element, region)
+            })
+            .collect()
+    }
+
+    /// Rough estimate in bytes of the memory used by the tree: nodes and the
+    /// allocated capacity of the element maps. Heap memory owned by `T` is not
+    /// included.
+    pub fn memory_usage(&self) -> usize {
+        let elements = self.elements.capacity() * mem::size_of::<(u64, (T, Rect))>()
+            + self.eviction_order.capacity() * mem::size_of::<u64>();
+        let nodes: usize = self
+            .nodes()
+            .map(|node| {
+                mem::size_of::<Node>() + node.elements.capacity() * mem::size_of::<(u64, Rect)>()
+            })
+            .sum();
+
+        // The root node is stored inline and already counted by `nodes`.
+        mem::size_of::<Self>() - mem::size_of::<Node>() + elements + nodes
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        self.elements.shrink_to_fit();
+        self.eviction_order.shrink_to_fit();
+        self.root.shrink_to_fit();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u64, &T, Rect)> {
+        self.elements
+            .iter()
+            .map(|(id, (element, region))| (*id, element, *region))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (u64, &mut T, Rect)> {
+        self.elements
+            .iter_mut()
+            .map(|(id, (element, region))| (*id, element, *region))
+    }
+
+    pub fn entries<'a>(&'a self) -> impl Iterator<Item = Entry<'a, T>> {
+        let iter = self.elements.keys().map(|id| Entry {
+            id: *id,
+            owner: self,
+        });
+
+        iter
+    }
+
+    /// Entries of the elements contained in `region`, like [`Quadtree::get_contained`].
+    pub fn entries_in_region<'a>(&'a self, region: Rect) -> impl Iterator<Item = Entry<'a, T>> {
+        self.contained_ids(region)
+            .into_iter()
+            .map(|id| Entry { id, owner: self })
+    }
+
+    /// Calls `f` with an [`EntryMut`] for every element. The ids are collected
+    /// up front, so `f` may move or remove entries (including ones it has not
+    /// been handed yet, which are then skipped).
+    ///
+    /// This replaces `entries_mut`, whose handles all borrowed the tree
+    /// mutably at once: write `tree.for_each_entry_mut(|mut entry| ..)`
+    /// instead of `for mut entry in tree.entries_mut() { .. }`.
+    pub fn for_each_entry_mut(&mut self, mut f: impl FnMut(EntryMut<'_, T>)) {
+        let ids: Vec<u64> = self.elements.keys().copied().collect();
+        for id in ids {
+            if self.elements.contains_key(&id) {
+                f(EntryMut { id, owner: self });
+            }
+        }
+    }
+
+    /// Deepest leaf whose region contains `point`, or `None` if the point is
+    /// outside the root region.
+    pub fn node_at_point(&self, point: (f32, f32)) -> Option<&Node> {
+        let (x, y) = point;
+        if !self.root.region.contains_point(x, y) {
+            return None;
+        }
+
+        let mut node = &self.root;
+        while let Some(children) = &node.children {
+            node = children
+                .iter()
+                .find(|child| child.region.contains_point(x, y))?;
+        }
+
+        Some(node)
+    }
+
+    /// Depth of the node holding `id`, which is above the leaves when the
+    /// element straddles a split line. `None` for unknown ids.
+    pub fn depth_of(&self, id: u64) -> Option<u32> {
+        let region = self.elements.get(&id)?.1;
+
+        let mut node = &self.root;
+        while !node.elements.contains_key(&id) {
+            node = node
+                .children
+                .as_ref()?
+                .iter()
+                .find(|child| child.region.contains(&region))?;
+        }
+
+        Some(node.depth)
+    }
+
+    /// Bottom-up aggregate over the node tree, e.g. Barnes-Hut mass summaries.
+    /// `local` summarizes the elements stored directly in a node (a leaf's
+    /// elements, or the straddling ones of an inner node), and `combine` merges
+    /// an inner node's local summary with the summaries of its four children.
+    pub fn fold_nodes<A>(
+        &self,
+        mut local: impl FnMut(&Node, &[(u64, &T, Rect)]) -> A,
+        mut combine: impl FnMut(&Node, A, [A; 4]) -> A,
+    ) -> A {
+        self.fold_node(&self.root, &mut local, &mut combine)
+    }
+
+    fn fold_node<A>(
+        &self,
+        node: &Node,
+        local: &mut impl FnMut(&Node, &[(u64, &T, Rect)]) -> A,
+        combine: &mut impl FnMut(&Node, A, [A; 4]) -> A,
+    ) -> A {
+        let elements: Vec<(u64, &T, Rect)> = node
+            .elements
+            .iter()
+            .map(|(id, region)| (*id, &self.elements[id].0, *region))
+            .collect();
+        let own = local(node, &elements);
+
+        match &node.children {
+            Some(children) => {
+                let children = [
+                    self.fold_node(&children[0], local, combine),
+                    self.fold_node(&children[1], local, combine),
+                    self.fold_node(&children[2], local, combine),
+                    self.fold_node(&children[3], local, combine),
+                ];
+                combine(node, own, children)
+            }
+            None => own,
+        }
+    }
+
+    /// Counts element centers in a uniform `cols` x `rows` grid over the root
+    /// region, row-major (`index = row * cols + col`, row 0 at the root's `y`).
+    /// Cells are half-open, so a center on a shared boundary counts in the cell
+    /// after it, except on the root's far edges which belong to the last cell.
+    pub fn occupancy_grid(&self, cols: usize, rows: usize) -> Vec<usize> {
+        let mut grid = vec![0; cols * rows];
+        if grid.is_empty() {
+            return grid;
+        }
+
+        let root = self.root.region;
+        let cell = |value: f32, min: f32, extent: f32, count: usize| {
+            // The cast truncates, which floors once negatives are clamped to 0.
+            let index = (value - min) / extent * count as f32;
+            (index.max(0.0) as usize).min(count - 1)
+        };
+
+        for (_, region) in self.elements.values() {
+            let (cx, cy) = region.center();
+            let col = cell(cx, root.x, root.w, cols);
+            let row = cell(cy, root.y, root.h, rows);
+            grid[row * cols + col] += 1;
+        }
+
+        grid
+    }
+
+    /// Renders [`Quadtree::occupancy_grid`] as text for debugging: one line per
+    /// row, `#` for cells holding an element center and `.` for empty ones.
+    pub fn to_ascii(&self, width: usize, height: usize) -> String {
+        let mut ascii = String::with_capacity((width + 1) * height);
+        if width == 0 {
+            return ascii;
+        }
+
+        for row in self.occupancy_grid(width, height).chunks(width) {
+            ascii.extend(row.iter().map(|count| if *count > 0 { '#' } else { '.' }));
+            ascii.push('\n');
+        }
+
+        ascii
+    }
+
+    /// Elements sorted top to bottom, then left to right, by the top left
+    /// corner of their region. Ties are broken by id.
+    pub fn iter_scanline(&self) -> impl Iterator<Item = (u64, &T)> {
+        let mut entries: Vec<(&Rect, u64, &T)> = self
+            .elements
+            .iter()
+            .map(|(id, (element, region))| (region, *id, element))
+            .collect();
+        entries.sort_by(|a, b| {
+            a.0.y
+                .total_cmp(&b.0.y)
+                .then(a.0.x.total_cmp(&b.0.x))
+                .then(a.1.cmp(&b.1))
+        });
+
+        entries.into_iter().map(|(_, id, element)| (id, element))
+    }
+
+    /// Elements ordered along the Z-order curve of their region centers,
+    /// quantized to the root region. Ties are broken by id.
+    pub fn entries_morton(&self) -> Vec<(u64, &T)> {
+        let root = self.root.region;
+        let quantize = |value: f32, min: f32, extent: f32| {
+            (((value - min) / extent).clamp(0.0, 1.0) * u32::MAX as f32) as u32
+        };
+
+        let mut entries: Vec<(u64, u64, &T)> = self
+            .elements
+            .iter()
+            .map(|(id, (element, region))| {
+                let (cx, cy) = region.center();
+                let code =
+                    morton_encode(quantize(cx, root.x, root.w), quantize(cy, root.y, root.h));
+                (code, *id, element)
+            })
+            .collect();
+        entries.sort_by_key(|(code, id, _)| (*code, *id));
+
+        entries
+            .into_iter()
+            .map(|(_, id, element)| (id, element))
+            .collect()
+    }
+
+    pub fn root(&self) -> &Node {
+        &self.root
+    }
+
+    /// Every node with its path from the root, the child indices (see
+    /// [`Node::children`]) taken at each level. The root's path is empty.
+    pub fn nodes_with_path(&self) -> impl Iterator<Item = (Vec<u8>, &Node)> {
+        let mut nodes_to_process = vec![(Vec::new(), &self.root)];
+        iter::from_fn(move || {
+            let (path, node) = nodes_to_process.pop()?;
+            if let Some(children) = &node.children {
+                for (index, child) in children.iter().enumerate().rev() {
+                    let mut child_path = path.clone();
+                    child_path.push(index as u8);
+                    nodes_to_process.push((child_path, child));
+                }
+            }
+
+            Some((path, node))
+        })
+    }
+
+    pub fn nodes<'a>(&'a self) -> NodeIter<'a> {
+        NodeIter {
+            nodes_to_process: vec![&self.root],
+        }
+    }
+
+    /// The elements stored directly in `node`, which should come from this tree.
+    pub fn node_elements<'a>(
+        &'a self,
+        node: &'a Node,
+    ) -> impl Iterator<Item = (u64, &'a T, Rect)> + 'a {
+        node.elements
+            .iter()
+            .map(|(id, region)| (*id, &self.elements[id].0, *region))
+    }
+
+    /// Moves the element `id` to `new_region`, returns `false` for unknown ids.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `new_region` is not contained in the root region.
+    pub fn reinsert(&mut self, id: u64, new_region: Rect) -> bool {
+        let Some((_, old_region)) = self.elements.get(&id) else {
+            return false;
+        };
+
+        self.move_element(id, *old_region, new_region);
+
+        true
+    }
+
+    pub fn swap_positions(&mut self, a: u64, b: u64) -> bool {
+        let (Some((_, region_a)), Some((_, region_b))) =
+            (self.elements.get(&a), self.elements.get(&b))
+        else {
+            return false;
+        };
+        let (region_a, region_b) = (*region_a, *region_b);
+
+        if a == b {
+            return true;
+        }
+
+        let fuse_threshold = self.fuse_threshold();
+        self.root.remove(a, region_a, fuse_threshold);
+        self.root.remove(b, region_b, fuse_threshold);
+
+        self.root
+            .insert(a, region_b, self.max_node_capacity, self.depth_limit());
+        self.root
+            .insert(b, region_a, self.max_node_capacity, self.depth_limit());
+
+        self.elements.get_mut(&a).unwrap().1 = region_b;
+        self.elements.get_mut(&b).unwrap().1 = region_a;
+
+        true
+    }
+
+    /// Applies `f` to every value, keeping the ids, regions and nodes as they are.
+    pub fn map<U>(self, mut f: impl FnMut(T) -> U) -> Quadtree<U> {
+        Quadtree {
+            max_node_capacity: self.max_node_capacity,
+            root: self.root,
+            elements: self
+                .elements
+                .into_iter()
+                .map(|(id, (element, region))| (id, (f(element), region)))
+                .collect(),
+            next_id: self.next_id,
+            element_limit: self.element_limit,
+            eviction_order: self.eviction_order,
+            fuse_threshold: self.fuse_threshold,
+            max_depth: self.max_depth,
+        }
+    }
+
+    /// Moves every element of `other` into `self`, assigning them fresh ids.
+    /// Returns the mapping from the ids in `other` to the new ids in `self`.
+    ///
+    /// # Panics
+    ///
+    /// Panics, like [`Quadtree::insert`], if an element of `other` is not
+    /// contained in the root region of `self`.
+    pub fn merge(&mut self, other: Quadtree<T>) -> HashMap<u64, u64> {
+        let mut elements: Vec<(u64, (T, Rect))> = other.elements.into_iter().collect();
+        elements.sort_by_key(|(id, _)| *id);
+
+        let mut id_map = HashMap::with_capacity(elements.len());
+        for (old_id, (element, region)) in elements {
+            let new_id = self.insert(element, region);
+            id_map.insert(old_id, new_id);
+        }
+
+        id_map
+    }
+
+    /// Rebuilds the tree with the elements' bounding box, padded by 1% of its
+    /// largest side and kept within the current root, as the new root region.
+    /// Ids and regions are preserved. Does nothing on an empty tree.
+    pub fn shrink_root(&mut self) {
+        let Some(bounds) = self.bounding_box() else {
+            return;
+        };
+
+        let padding = bounds.w.max(bounds.h) * 0.01;
+        let padded = Rect::new(
+            bounds.x - padding,
+            bounds.y - padding,
+            bounds.w + 2.0 * padding,
+            bounds.h + 2.0 * padding,
+        );
+
+        self.root.region = padded.clamp(&self.root.region);
+        self.rebalance();
+    }
+
+    /// Splits the tree at the vertical line `x`: elements whose center is left
+    /// of `x` go to the first tree, the others to the second. Each root is its
+    /// half of the current root, grown to fit elements crossing the line. Ids
+    /// are preserved.
+    pub fn split_at_x(self, x: f32) -> (Quadtree<T>, Quadtree<T>) {
+        let root = self.root.region;
+        let x = x.clamp(root.x, root.x + root.w);
+        let left = Rect::new(root.x, root.y, x - root.x, root.h);
+        let right = Rect::new(x, root.y, root.x + root.w - x, root.h);
+
+        self.split(left, right, |region| region.center().0 < x)
+    }
+
+    /// Splits the tree at the horizontal line `y`, like [`Quadtree::split_at_x`].
+    pub fn split_at_y(self, y: f32) -> (Quadtree<T>, Quadtree<T>) {
+        let root = self.root.region;
+        let y = y.clamp(root.y, root.y + root.h);
+        let top = Rect::new(root.x, root.y, root.w, y - root.y);
+        let bottom = Rect::new(root.x, y, root.w, root.y + root.h - y);
+
+        self.split(top, bottom, |region| region.center().1 < y)
+    }
+
+    fn split(
+        self,
+        first_root: Rect,
+        second_root: Rect,
+        goes_first: impl Fn(&Rect) -> bool,
+    ) -> (Quadtree<T>, Quadtree<T>) {
+        let (first, second): (Vec<_>, Vec<_>) = self
+            .elements
+            .into_iter()
+            .partition(|(_, (_, region))| goes_first(region));
+
+        let build = |root: Rect, elements: Vec<(u64, (T, Rect))>| {
+            let root = elements
+                .iter()
+                .fold(root, |root, (_, (_, region))| root.union(region));
+
+            let mut quadtree = Quadtree {
+                element_limit: self.element_limit,
+                fuse_threshold: self.fuse_threshold,
+                max_depth: self.max_depth,
+                ..Quadtree::new(root, self.max_node_capacity)
+            };
+            for (id, (element, region)) in elements {
+                quadtree.insert_with_id(id, element, region);
+            }
+            quadtree.next_id = quadtree.next_id.max(self.next_id);
+
+            quadtree
+        };
+
+        (build(first_root, first), build(second_root, second))
+    }
+
+    /// Subdivides every node overlapping `region` down to `depth` (at most the
+    /// maximum depth), so a bulk insert of clustered elements there doesn't
+    /// subdivide them one by one.
+    /// The extra nodes may be empty; removals can fuse them again.
+    pub fn pre_subdivide(&mut self, region: Rect, depth: u32) {
+        let depth = depth.min(self.depth_limit());
+        if region.overlaps(&self.root.region) {
+            self.root
+                .pre_subdivide(region, depth, self.max_node_capacity);
+        }
+    }
+
+    /// Turns every node with an empty subtree back into a leaf, which is
+    /// cheaper than a [`Quadtree::rebalance`] when only empty nodes are left
+    /// over, e.g. after [`Quadtree::pre_subdivide`] or with a low fuse threshold.
+    pub fn prune_empty_nodes(&mut self) {
+        self.root.prune_empty();
+    }
+
+    pub fn rebalance(&mut self) {
+        self.root = Node::new(self.root.region);
+
+        for (id, (_, region)) in self.elements.iter() {
+            self.root
+                .insert(*id, *region, self.max_node_capacity, self.depth_limit());
+        }
+    }
+
+    /// Number of elements held by non-leaf nodes because their region straddles
+    /// a split line and no single child can contain it.
+    pub fn straddling_count(&self) -> usize {
+        self.nodes()
+            .filter(|node| node.is_node())
+            .map(|node| node.elements.len())
+            .sum()
+    }
+
+    pub fn capacity_report(&self) -> CapacityReport {
+        let mut report = CapacityReport {
+            leaf_count: 0,
+            min_elements_per_leaf: usize::MAX,
+            max_elements_per_leaf: 0,
+            avg_elements_per_leaf: 0.0,
+            overfull_nodes: 0,
+        };
+        let mut leaf_elements = 0;
+
+        for node in self.nodes() {
+            let count = node.elements.len();
+            if count > self.max_node_capacity {
+                report.overfull_nodes += 1;
+            }
+
+            if node.is_leaf() {
+                report.leaf_count += 1;
+                report.min_elements_per_leaf = report.min_elements_per_leaf.min(count);
+                report.max_elements_per_leaf = report.max_elements_per_leaf.max(count);
+                leaf_elements += count;
+            }
+        }
+
+        // The root is always counted, so there is at least one leaf.
+        report.avg_elements_per_leaf = leaf_elements as f32 / report.leaf_count as f32;
+        report
+    }
+
+    /// Checks the node bookkeeping against the elements: every element is held
+    /// by exactly one node with the same region, that node's region contains
+    /// it, and each node's size counts its whole subtree. Meant for tests.
+    pub fn validate(&self) -> Result<(), String> {
+        let mut seen = HashSet::new();
+        self.validate_node(&self.root, &mut seen)?;
+
+        if seen.len() != self.elements.len() {
+            return Err(format!(
+                "{} elements are stored but {} are held by nodes",
+                self.elements.len(),
+                seen.len()
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn validate_node(&self, node: &Node, seen: &mut HashSet<u64>) -> Result<usize, String> {
+        for (id, region) in node.elements.iter() {
+            match self.elements.get(id) {
+                Some((_, stored)) if stored == region => {}
+                Some((_, stored)) => {
+                    return Err(format!(
+                        "Element {} has region {:?} but its node holds {:?}",
+                        id, stored, region
+                    ))
+                }
+                None => {
+                    return Err(format!(
+                        "Node {:?} holds unknown element {}",
+                        node.region, id
+                    ))
+                }
+            }
+
+            if !node.region.contains(region) {
+                return Err(format!(
+                    "Element {} with region {:?} is outside its node {:?}",
+                    id, region, node.region
+                ));
+            }
+
+            if !seen.insert(*id) {
+                return Err(format!("Element {} is held by several nodes", id));
+            }
+        }
+
+        let mut size = node.elements.len();
+        if let Some(children) = &node.children {
+            for child in children.iter() {
+                size += self.validate_node(child, seen)?;
+            }
+        }
+
+        if size != node.size {
+            return Err(format!(
+                "Node {:?} has size {} but holds {} elements",
+                node.region, node.size, size
+            ));
+        }
+
+        Ok(size)
+    }
+
+    /// Node and element counts indexed by depth. Elements held above the
+    /// deepest levels are mostly straddling ones.
+    pub fn stats_per_depth(&self) -> Vec<DepthStats> {
+        let mut stats = vec![DepthStats::default(); self.height() as usize + 1];
+        for node in self.nodes() {
+            let depth = &mut stats[node.depth as usize];
+            depth.node_count += 1;
+            depth.element_count += node.elements.len();
+        }
+
+        stats
+    }
+
+    fn contained_ids(&self, region: Rect) -> Vec<u64> {
+        match self.root.region.intersection(&region) {
+            Some(region) => self.root.get_contained(region),
+            None => Vec::new(),
+        }
+    }
+
+    fn overlapped_where(&self, region: Rect, keep: impl Fn(u64) -> bool) -> Vec<(u64, &T)> {
+        let mut result = Vec::new();
+        self.root.visit_overlapped(region, &mut |id| {
+            if keep(id) {
+                result.push((id, &self.elements[&id].0));
+            }
+
+            true
+        });
+
+        result
+    }
+
+    fn overlapped_ids(&self, region: Rect) -> Vec<u64> {
+        match self.root.region.intersection(&region) {
+            Some(region) => self.root.get_overlapped(region),
+            None => Vec::new(),
+        }
+    }
+
+    fn elements_mut(&mut self, ids: Vec<u64>) -> Vec<&mut T> {
+        let ids: HashSet<u64> = ids.into_iter().collect();
+
+        self.elements
+            .iter_mut()
+            .filter(|(id, _)| ids.contains(*id))
+            .map(|(_, (element, _))| element)
+            .collect()
+    }
+
+    fn evict_for_insert(&mut self) -> Option<(u64, T, Rect)> {
+        let limit = self.element_limit?;
+        if self.size() < limit {
+            return None;
+        }
+
+        while let Some(Reverse(oldest)) = self.eviction_order.pop() {
+            if let Some((element, region)) = self.remove(oldest) {
+                return Some((oldest, element, region));
+            }
+        }
+
+        None
+    }
+
+    fn track_for_eviction(&mut self, id: u64) {
+        if self.element_limit.is_none() {
+            return;
+        }
+
+        // Rebuild once removed ids make up half the heap, so it stays within
+        // twice the element count.
+        if self.eviction_order.len() >= 2 * self.elements.len() {
+            self.eviction_order = self.elements.keys().map(|id| Reverse(*id)).collect();
+        } else {
+            self.eviction_order.push(Reverse(id));
+        }
+    }
+
+    fn check_region(&self, region: Rect) -> Result<(), QuadtreeError> {
+        if !region.is_valid() {
+            return Err(QuadtreeError::InvalidRegion(region));
+        }
+
+        if !self.root.region.contains(&region) {
+            return Err(QuadtreeError::OutOfBounds(region));
+        }
+
+        Ok(())
+    }
+
+    fn move_element(&mut self, id: u64, old_region: Rect, new_region: Rect) {
+        assert_valid_region(new_region);
+
+        let fuse_threshold = self.fuse_threshold();
+        self.root.move_element(
+            id,
+            old_region,
+            new_region,
+            self.max_node_capacity,
+            self.depth_limit(),
+            fuse_threshold,
+        );
+
+        self.elements.get_mut(&id).unwrap().1 = new_region;
+    }
+}
+
+fn assert_valid_region(region: Rect) {
+    assert!(
+        region.is_valid(),
+        "Region {:?} has non-finite coordinates or a negative size",
+        region
+    );
+}
+
+impl<T> Quadtree<T>
+where
+    T: PartialEq,
+{
+    pub fn contains(&self, element: &T) -> bool {
+        self.elements.values().any(|(e, _)| e == element)
+    }
+
+    /// Removes an element equal to `value`. With duplicates, which one is
+    /// removed is unspecified.
+    pub fn remove_value(&mut self, value: &T) -> Option<(u64, T, Rect)> {
+        let id = self.find_id_of(value)?;
+
+        self.remove(id)
+            .map(|(element, region)| (id, element, region))
+    }
+
+    /// Id of an element equal to `value`. With duplicates, which one is found
+    /// is unspecified, see [`Quadtree::find_all_ids_of`].
+    pub fn find_id_of(&self, value: &T) -> Option<u64> {
+        self.elements
+            .iter()
+            .find(|(_, (element, _))| element == value)
+            .map(|(id, _)| *id)
+    }
+
+    /// Ids of all elements equal to `value`, in increasing order.
+    pub fn find_all_ids_of(&self, value: &T) -> Vec<u64> {
+        let mut ids: Vec<u64> = self
+            .elements
+            .iter()
+            .filter(|(_, (element, _))| element == value)
+            .map(|(id, _)| *id)
+            .collect();
+        ids.sort();
+
+        ids
+    }
+}
+
+impl<T> Quadtree<T>
+where
+    T: Clone,
+{
+    /// Inserts clones of the elements of `other` contained in `region`, with
+    /// fresh ids, and returns how many were copied. `other` is left as is.
+    ///
+    /// # Panics
+    ///
+    /// Panics, like [`Quadtree::merge`], if a copied element is not contained
+    /// in the root region of `self`.
+    pub fn insert_all_contained(&mut self, other: &Quadtree<T>, region: Rect) -> usize {
+        let mut ids = other.contained_ids(region);
+        ids.sort();
+
+        for id in ids.iter() {
+            let (element, element_region) = &other.elements[id];
+            self.insert(element.clone(), *element_region);
+        }
+
+        ids.len()
+    }
+}
+
+/// Two quadtrees are equal when they hold the same multiset of `(value, region)`
+/// pairs. Ids, node layout, root region and node capacity are not compared.
+impl<T> PartialEq for Quadtree<T>
+where
+    T: PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        if self.size() != other.size() {
+            return false;
+        }
+
+        let others: Vec<&(T, Rect)> = other.elements.values().collect();
+        let mut matched = vec![false; others.len()];
+
+        self.elements.values().all(|element| {
+            let found = others
+                .iter()
+                .enumerate()
+                .position(|(i, other)| !matched[i] && *other == element);
+
+            match found {
+                Some(i) => {
+                    matched[i] = true;
+                    true
+                }
+                None => false,
+            }
+        })
+    }
+}
+
+impl<T> Default for Quadtree<T> {
+    fn default() -> Self {
+        QuadtreeBuilder::default().build()
+    }
+}
+
+impl<'a> Iterator for NodeIter<'a> {
+    type Item = &'a Node;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(node) = self.nodes_to_process.pop() {
+            if let Some(children) = &node.children {
+                for child in children.as_ref() {
+                    self.nodes_to_process.push(child);
+                }
+            }
+
+            return Some(node);
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+
+    use crate::rect::Rect;
+
+    use super::*;
+
+    #[test]
+    fn create_empty() {
+        let quadtree: Quadtree<i32> = Quadtree::default();
+
+        assert!(quadtree.is_empty());
+        assert_eq!(quadtree.size(), 0);
+        assert!(quadtree.root.is_leaf());
+    }
+
+    #[test]
+    fn build_with_every_parameter() {
+        let quadtree: Quadtree<i32> = QuadtreeBuilder::new()
+            .region(Rect::new(0.0, 0.0, 100.0, 100.0))
+            .max_node_capacity(4)
+            .max_depth(3)
+            .fuse_threshold(2)
+            .element_limit(10)
+            .capacity(16)
+            .build();
+
+        assert_eq!(quadtree.root().region(), Rect::new(0.0, 0.0, 100.0, 100.0));
+        assert_eq!(quadtree.max_node_capacity(), 4);
+        assert_eq!(quadtree.max_depth(), Some(3));
+        assert_eq!(quadtree.fuse_threshold(), 2);
+        assert_eq!(quadtree.element_limit, Some(10));
+        assert!(quadtree.capacity() >= 16);
+    }
+
+    #[test]
+    fn builder_defaults_match_default() {
+        let built: Quadtree<i32> = QuadtreeBuilder::new().build();
+        let default: Quadtree<i32> = Quadtree::default();
+
+        assert_eq!(built.root().region(), default.root().region());
+        assert_eq!(built.max_node_capacity(), default.max_node_capacity());
+        assert_eq!(built.max_depth(), None);
+    }
+
+    #[test]
+    fn max_depth_stops_subdivision() {
+        let mut quadtree = QuadtreeBuilder::new()
+            .region(Rect::new(0.0, 0.0, 100.0, 100.0))
+            .max_node_capacity(1)
+            .max_depth(2)
+            .build();
+        for i in 0..10 {
+            quadtree.insert(i, Rect::new(1.0 + i as f32, 1.0, 0.5, 0.5));
+        }
+
+        assert_eq!(quadtree.height(), 2);
+        assert_eq!(quadtree.node_at_point((1.0, 1.0)).unwrap().size(), 10);
+        assert_eq!(quadtree.capacity_report().overfull_nodes, 1);
+        assert_eq!(quadtree.validate(), Ok(()));
+
+        quadtree.pre_subdivide(Rect::new(0.0, 0.0, 100.0, 100.0), 5);
+        assert_eq!(quadtree.height(), 2);
+    }
+
+    #[test]
+    fn create_square() {
+        let mut quadtree = Quadtree::new_square((10.0, 20.0), 50.0, 1);
+        assert_eq!(
+            quadtree.root.region(),
+            Rect::new(-40.0, -30.0, 100.0, 100.0)
+        );
+
+        quadtree.insert(1, Rect::new(0.0, 0.0, 1.0, 1.0));
+        quadtree.insert(2, Rect::new(30.0, 30.0, 1.0, 1.0));
+        assert!(quadtree
+            .nodes()
+            .all(|node| node.region().aspect_ratio() == 1.0));
+    }
+
+    // Insertion
+    #[test]
+    fn insert_one_element() {
+        let mut quadtree = Quadtree::default();
+        quadtree.insert(42, Rect::new(10.0, 10.0, 10.0, 10.0));
+
+        assert!(quadtree.contains(&42));
+        assert_eq!(quadtree.size(), 1);
+        assert!(!quadtree.is_empty());
+    }
+
+    #[test]
+    fn insert_two_elements() {
+        let mut quadtree = Quadtree::default();
+        quadtree.insert(42, Rect::new(10.0, 10.0, 10.0, 10.0));
+        quadtree.insert(5, Rect::new(50.0, 20.0, 40.0, 10.0));
+
+        assert!(quadtree.contains(&42));
+        assert!(quadtree.contains(&5));
+        assert_eq!(quadtree.size(), 2);
+    }
+
+    #[test]
+    fn insert_with_custom_id() {
+        let mut quadtree = Quadtree::default();
+        let displaced = quadtree.insert_with_id(10, 42, Rect::new(10.0, 10.0, 10.0, 10.0));
+
+        assert_eq!(displaced, None);
+        assert_eq!(quadtree.entry(10).value(), &42);
+        assert_eq!(quadtree.insert(5, Rect::new(20.0, 20.0, 10.0, 10.0)), 11);
+    }
+
+    #[test]
+    fn insert_with_existing_id_displaces_value() {
+        let mut quadtree = Quadtree::default();
+        let id = quadtree.insert(42, Rect::new(10.0, 10.0, 10.0, 10.0));
+        let displaced = quadtree.insert_with_id(id, 5, Rect::new(-20.0, -20.0, 10.0, 10.0));
+
+        assert_eq!(displaced, Some(42));
+        assert_eq!(quadtree.size(), 1);
+        assert_eq!(quadtree.root.size(), 1);
+        assert_eq!(
+            quadtree.get_contained(Rect::new(-20.0, -20.0, 10.0, 10.0)),
+            vec![&5]
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn insert_with_max_id() {
+        let mut quadtree = Quadtree::default();
+        quadtree.insert_with_id(u64::MAX, 1, Rect::new(10.0, 10.0, 5.0, 5.0));
+    }
+
+    #[test]
+    fn insert_with_largest_valid_id() {
+        let mut quadtree = Quadtree::default();
+        quadtree.insert_with_id(u64::MAX - 1, 1, Rect::new(10.0, 10.0, 5.0, 5.0));
+
+        assert_eq!(quadtree.entry(u64::MAX - 1).value(), &1);
+        assert_eq!(quadtree.next_id, u64::MAX);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn insert_after_exhausting_ids() {
+        let mut quadtree = Quadtree::default();
+        quadtree.insert_with_id(0, 0, Rect::new(10.0, 10.0, 5.0, 5.0));
+        quadtree.insert_with_id(u64::MAX - 1, 1, Rect::new(20.0, 20.0, 5.0, 5.0));
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            quadtree.insert(2, Rect::new(30.0, 30.0, 5.0, 5.0))
+        }));
+
+        assert!(result.is_err());
+        assert_eq!(quadtree.size(), 2);
+        assert_eq!(quadtree.entry(0).value(), &0);
+        assert_eq!(quadtree.validate(), Ok(()));
+    }
+
+    #[test]
+    #[should_panic]
+    fn insert_nan_region() {
+        let mut quadtree = Quadtree::default();
+        quadtree.insert(1, Rect::new(f32::NAN, 10.0, 5.0, 5.0));
+    }
+
+    #[test]
+    #[should_panic]
+    fn insert_negative_size_region() {
+        let mut quadtree = Quadtree::default();
+        quadtree.insert(1, Rect::new(10.0, 10.0, -5.0, 5.0));
+    }
+
+    #[test]
+    fn try_insert_rejects_invalid_regions() {
+        let mut quadtree = Quadtree::default();
+        let nan = Rect::new(10.0, f32::NAN, 5.0, 5.0);
+        let negative = Rect::new(10.0, 10.0, 5.0, -5.0);
+        let outside = Rect::new(500.0, 10.0, 5.0, 5.0);
+
+        assert!(matches!(
+            quadtree.try_insert(1, nan),
+            Err(QuadtreeError::InvalidRegion(_))
+        ));
+        assert_eq!(
+            quadtree.try_insert(1, negative),
+            Err(QuadtreeError::InvalidRegion(negative))
+        );
+        assert_eq!(
+            quadtree.try_insert(1, outside),
+            Err(QuadtreeError::OutOfBounds(outside))
+        );
+        assert!(quadtree.is_empty());
+
+        assert_eq!(
+            quadtree.try_insert(1, Rect::new(10.0, 10.0, 5.0, 5.0)),
+            Ok(0)
+        );
+    }
+
+    #[test]
+    fn insert_evicts_oldest_when_limit_reached() {
+        let mut quadtree = Quadtree::with_element_limit(Rect::new(0.0, 0.0, 100.0, 100.0), 2, 3);
+        let first = quadtree.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+        quadtree.insert(2, Rect::new(20.0, 20.0, 5.0, 5.0));
+        quadtree.insert(3, Rect::new(30.0, 30.0, 5.0, 5.0));
+
+        let (_, evicted) = quadtree.insert_evicting(4, Rect::new(40.0, 40.0, 5.0, 5.0));
+
+        assert_eq!(evicted, Some((first, 1, Rect::new(10.0, 10.0, 5.0, 5.0))));
+        assert_eq!(quadtree.size(), 3);
+        assert_eq!(quadtree.root.size(), 3);
+        assert!(!quadtree.contains(&1));
+        assert!(quadtree.contains(&4));
+    }
+
+    #[test]
+    fn insert_with_id_evicts_lowest_id() {
+        let mut quadtree = Quadtree::with_element_limit(Rect::new(0.0, 0.0, 100.0, 100.0), 2, 2);
+        quadtree.insert_with_id(10, 1, Rect::new(10.0, 10.0, 5.0, 5.0));
+        quadtree.insert_with_id(5, 2, Rect::new(20.0, 20.0, 5.0, 5.0));
+
+        let (displaced, evicted) =
+            quadtree.insert_with_id_evicting(7, 3, Rect::new(30.0, 30.0, 5.0, 5.0));
+
+        assert_eq!(displaced, None);
+        assert_eq!(evicted, Some((5, 2, Rect::new(20.0, 20.0, 5.0, 5.0))));
+        assert!(quadtree.contains(&1));
+        assert!(quadtree.contains(&3));
+    }
+
+    #[test]
+    fn eviction_skips_removed_ids() {
+        let mut quadtree = Quadtree::with_element_limit(Rect::new(0.0, 0.0, 100.0, 100.0), 2, 2);
+        let first = quadtree.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+        let second = quadtree.insert(2, Rect::new(20.0, 20.0, 5.0, 5.0));
+        quadtree.remove(first);
+        for _ in 0..20 {
+            let id = quadtree.insert(3, Rect::new(30.0, 30.0, 5.0, 5.0));
+            quadtree.remove(id);
+        }
+        quadtree.insert(4, Rect::new(40.0, 40.0, 5.0, 5.0));
+
+        let (_, evicted) = quadtree.insert_evicting(5, Rect::new(50.0, 50.0, 5.0, 5.0));
+
+        assert_eq!(evicted, Some((second, 2, Rect::new(20.0, 20.0, 5.0, 5.0))));
+        assert!(quadtree.eviction_order.len() <= 2 * quadtree.size());
+        assert_eq!(quadtree.validate(), Ok(()));
+    }
+
+    #[test]
+    fn get_or_insert_with_reuses_exact_region() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 1);
+        let region = Rect::new(10.0, 10.0, 5.0, 5.0);
+        let id = quadtree.get_or_insert_with(region, || 1);
+
+        assert_eq!(quadtree.get_or_insert_with(region, || 2), id);
+        assert_ne!(
+            quadtree.get_or_insert_with(Rect::new(10.0, 10.0, 5.0, 4.0), || 3),
+            id
+        );
+        assert_eq!(quadtree.size(), 2);
+        assert!(!quadtree.contains(&2));
+    }
+
+    #[test]
+    fn insert_without_limit_never_evicts() {
+        let mut quadtree = Quadtree::default();
+        for i in 0..10 {
+            let (_, evicted) = quadtree.insert_evicting(i, Rect::new(10.0, 10.0, 5.0, 5.0));
+            assert_eq!(evicted, None);
+        }
+
+        assert_eq!(quadtree.size(), 10);
+    }
+
+    #[test]
+    fn insert_many_returns_ids_in_order() {
+        let mut quadtree = Quadtree::default();
+        let ids = quadtree.insert_many(vec![
+            (1, Rect::new(10.0, 10.0, 5.0, 5.0)),
+            (2, Rect::new(-10.0, 10.0, 5.0, 5.0)),
+            (3, Rect::new(10.0, -10.0, 5.0, 5.0)),
+        ]);
+
+        assert_eq!(ids.len(), 3);
+        assert_eq!(quadtree.size(), 3);
+        for (id, value) in ids.into_iter().zip([1, 2, 3]) {
+            assert_eq!(quadtree.entry(id).value(), &value);
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Sprite {
+        x: f32,
+        y: f32,
+    }
+
+    impl Bounded for Sprite {
+        fn bounds(&self) -> Rect {
+            Rect::new(self.x, self.y, 2.0, 2.0)
+        }
+    }
+
+    #[test]
+    fn insert_bounded_uses_element_bounds() {
+        let mut quadtree = Quadtree::default();
+        let id = quadtree.insert_bounded(Sprite { x: 10.0, y: 10.0 });
+
+        assert_eq!(quadtree.entry(id).region(), Rect::new(10.0, 10.0, 2.0, 2.0));
+    }
+
+    #[test]
+    fn update_bounded_moves_entry() {
+        let mut quadtree = Quadtree::default();
+        let id = quadtree.insert_bounded(Sprite { x: 10.0, y: 10.0 });
+
+        quadtree
+            .entry_mut(id)
+            .update_bounded(|sprite| sprite.x = -50.0);
+
+        assert_eq!(
+            quadtree.entry(id).region(),
+            Rect::new(-50.0, 10.0, 2.0, 2.0)
+        );
+        assert_eq!(
+            quadtree.get_contained(Rect::new(-50.0, 10.0, 2.0, 2.0)),
+            vec![&Sprite { x: -50.0, y: 10.0 }]
+        );
+    }
+
+    #[test]
+    fn not_contains_not_inserted_element() {
+        let quadtree = Quadtree::default();
+        assert!(!quadtree.contains(&666));
+    }
+
+    // Centroid
+    #[test]
+    fn centroid_of_elements() {
+        let mut quadtree = Quadtree::default();
+        assert_eq!(quadtree.centroid(), None);
+        assert_eq!(quadtree.weighted_centroid(), None);
+
+        quadtree.insert(1, Rect::new(0.0, 0.0, 2.0, 2.0));
+        quadtree.insert(2, Rect::new(8.0, 18.0, 4.0, 4.0));
+
+        assert_eq!(quadtree.centroid(), Some((5.5, 10.5)));
+        assert_eq!(quadtree.weighted_centroid(), Some((8.2, 16.2)));
+    }
+
+    // Ids
+    #[test]
+    fn min_and_max_id() {
+        let mut quadtree = Quadtree::default();
+        assert_eq!(quadtree.min_id(), None);
+        assert_eq!(quadtree.max_id(), None);
+
+        let first = quadtree.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+        let second = quadtree.insert(2, Rect::new(10.0, 10.0, 5.0, 5.0));
+        let third = quadtree.insert(3, Rect::new(10.0, 10.0, 5.0, 5.0));
+        quadtree.remove(first);
+
+        assert_eq!(quadtree.min_id(), Some(second));
+        assert_eq!(quadtree.max_id(), Some(third));
+    }
+
+    // Straddling elements
+    #[test]
+    fn element_straddling_center_stays_in_root() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 2);
+        quadtree.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+        quadtree.insert(2, Rect::new(60.0, 60.0, 5.0, 5.0));
+        let straddling = quadtree.insert(3, Rect::new(45.0, 45.0, 10.0, 10.0));
+
+        assert!(quadtree.root.is_node());
+        assert!(quadtree.root.elements.contains_key(&straddling));
+        assert_eq!(quadtree.straddling_count(), 1);
+    }
+
+    #[test]
+    fn element_straddling_center_is_found_by_queries() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 2);
+        quadtree.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+        quadtree.insert(2, Rect::new(60.0, 60.0, 5.0, 5.0));
+        quadtree.insert(3, Rect::new(45.0, 45.0, 10.0, 10.0));
+
+        assert_eq!(
+            quadtree.get_overlapped(Rect::new(52.0, 52.0, 1.0, 1.0)),
+            vec![&3]
+        );
+        assert_eq!(
+            quadtree.get_contained(Rect::new(40.0, 40.0, 20.0, 20.0)),
+            vec![&3]
+        );
+    }
+
+    #[test]
+    fn no_straddling_in_leaf_root() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 2);
+        quadtree.insert(3, Rect::new(45.0, 45.0, 10.0, 10.0));
+
+        assert_eq!(quadtree.straddling_count(), 0);
+    }
+
+    #[test]
+    fn stats_per_depth() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 1);
+        quadtree.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+        quadtree.insert(2, Rect::new(30.0, 30.0, 5.0, 5.0));
+        quadtree.insert(3, Rect::new(45.0, 45.0, 10.0, 10.0));
+
+        assert_eq!(
+            quadtree.stats_per_depth(),
+            vec![
+                DepthStats {
+                    node_count: 1,
+                    element_count: 1
+                },
+                DepthStats {
+                    node_count: 4,
+                    element_count: 0
+                },
+                DepthStats {
+                    node_count: 4,
+                    element_count: 2
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn depth_of_straddling_and_bucketed() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 1);
+        let deep = quadtree.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+        quadtree.insert(2, Rect::new(30.0, 30.0, 5.0, 5.0));
+        let straddling = quadtree.insert(3, Rect::new(45.0, 45.0, 10.0, 10.0));
+
+        assert_eq!(quadtree.depth_of(deep), Some(2));
+        assert_eq!(quadtree.depth_of(straddling), Some(0));
+        assert_eq!(quadtree.depth_of(999), None);
+    }
+
+    #[test]
+    fn capacity_report_counts_leaves_and_overfull_nodes() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 2);
+        quadtree.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+        quadtree.insert(2, Rect::new(60.0, 10.0, 5.0, 5.0));
+        quadtree.insert(3, Rect::new(70.0, 10.0, 5.0, 5.0));
+        for i in 0..3 {
+            quadtree.insert(10 + i, Rect::new(45.0, 45.0, 10.0, 10.0));
+        }
+
+        let report = quadtree.capacity_report();
+
+        assert_eq!(report.leaf_count, 4);
+        assert_eq!(report.min_elements_per_leaf, 0);
+        assert_eq!(report.max_elements_per_leaf, 2);
+        assert_eq!(report.avg_elements_per_leaf, 0.75);
+        assert_eq!(report.overfull_nodes, 1);
+    }
+
+    // Element access
+    #[test]
+    fn get_no_element_in_empty() {
+        let quadtree: Quadtree<i32> = Quadtree::default();
+        assert_eq!(
+            quadtree.get_contained(Rect::new(10.0, 10.0, 10.0, 10.0)),
+            Vec::<&i32>::new()
+        )
+    }
+
+    // Element access contained
+    #[test]
+    fn get_contained_after_one_insertion() {
+        let mut quadtree: Quadtree<i32> = Quadtree::default();
+        quadtree.insert(42, Rect::new(10.0, 10.0, 10.0, 10.0));
+
+        assert_eq!(
+            quadtree.get_contained(Rect::new(10.0, 10.0, 10.0, 10.0)),
+            vec![&42]
+        )
+    }
+
+    #[test]
+    fn get_two_contained_element_after_two_insertions() {
+        let mut quadtree: Quadtree<i32> = Quadtree::default();
+        quadtree.insert(42, Rect::new(10.0, 10.0, 10.0, 10.0));
+        quadtree.insert(5, Rect::new(11.0, 14.0, 5.0, 2.0));
+
+        let elements = quadtree.get_contained(Rect::new(10.0, 10.0, 10.0, 10.0));
+
+        assert!(elements.contains(&&42));
+        assert!(elements.contains(&&5));
+    }
+
+    #[test]
+    fn get_only_one_contained_element_after_two_insertions() {
+        let mut quadtree: Quadtree<i32> = Quadtree::default();
+        quadtree.insert(42, Rect::new(10.0, 10.0, 10.0, 10.0));
+        quadtree.insert(5, Rect::new(15.0, 10.0, 10.0, 10.0));
+
+        assert_eq!(
+            quadtree.get_contained(Rect::new(10.0, 10.0, 10.0, 10.0)),
+            vec![&42]
+        )
+    }
+
+    #[test]
+    fn mutate_all_contained_elements() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 2);
+        for i in 0..10 {
+            quadtree.insert(i, Rect::new(i as f32 * 5.0, 10.0, 2.0, 2.0));
+        }
+
+        let elements = quadtree.get_contained_mut(Rect::new(0.0, 0.0, 100.0, 50.0));
+        assert_eq!(elements.len(), 10);
+        for element in elements {
+            *element += 100;
+        }
+
+        let mut values: Vec<i32> = quadtree.query().collect().into_iter().copied().collect();
+        values.sort();
+
+        assert_eq!(values, (100..110).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn query_partly_outside_root_is_clamped() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 2);
+        for i in 0..10 {
+            quadtree.insert(i, Rect::new(i as f32 * 10.0, i as f32 * 10.0, 5.0, 5.0));
+        }
+
+        let query = Rect::new(-50.0, -50.0, 100.0, 100.0);
+        let clamped = Rect::new(0.0, 0.0, 50.0, 50.0);
+
+        let mut contained = quadtree.get_contained(query);
+        let mut contained_clamped = quadtree.get_contained(clamped);
+        contained.sort();
+        contained_clamped.sort();
+        assert_eq!(contained, contained_clamped);
+
+        let mut overlapped = quadtree.get_overlapped(query);
+        let mut overlapped_clamped = quadtree.get_overlapped(clamped);
+        overlapped.sort();
+        overlapped_clamped.sort();
+        assert_eq!(overlapped, overlapped_clamped);
+    }
+
+    #[test]
+    fn query_outside_root_is_empty() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 2);
+        quadtree.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+
+        assert!(quadtree
+            .get_contained(Rect::new(200.0, 200.0, 10.0, 10.0))
+            .is_empty());
+        assert!(quadtree
+            .get_overlapped(Rect::new(200.0, 200.0, 10.0, 10.0))
+            .is_empty());
+    }
+
+    // Element access overlapped
+    #[test]
+    fn get_overlapped_excluding_self() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 1);
+        let me = quadtree.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+        let other = quadtree.insert(2, Rect::new(12.0, 12.0, 5.0, 5.0));
+        let third = quadtree.insert(3, Rect::new(14.0, 8.0, 5.0, 5.0));
+        let around = Rect::new(8.0, 8.0, 10.0, 10.0);
+
+        let mut found = quadtree.get_overlapped_excluding(around, me);
+        found.sort();
+        assert_eq!(found, vec![(other, &2), (third, &3)]);
+
+        let group = HashSet::from([me, third]);
+        assert_eq!(
+            quadtree.get_overlapped_excluding_set(around, &group),
+            vec![(other, &2)]
+        );
+    }
+
+    #[test]
+    fn partition_contained_and_partial() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 1);
+        quadtree.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+        quadtree.insert(2, Rect::new(18.0, 18.0, 5.0, 5.0));
+        quadtree.insert(3, Rect::new(80.0, 80.0, 5.0, 5.0));
+        quadtree.insert(4, Rect::new(0.0, 0.0, 2.0, 2.0));
+
+        let (mut contained, partial) =
+            quadtree.partition_by_region(Rect::new(0.0, 0.0, 20.0, 20.0));
+        contained.sort();
+
+        assert_eq!(contained, vec![&1, &4]);
+        assert_eq!(partial, vec![&2]);
+    }
+
+    #[test]
+    fn queries_into_reused_buffer() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 1);
+        quadtree.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+        quadtree.insert(2, Rect::new(18.0, 18.0, 5.0, 5.0));
+        quadtree.insert(3, Rect::new(80.0, 80.0, 5.0, 5.0));
+
+        let mut buffer = Vec::new();
+        quadtree.get_overlapped_into(Rect::new(0.0, 0.0, 20.0, 20.0), &mut buffer);
+        buffer.sort();
+        assert_eq!(buffer, vec![&1, &2]);
+
+        quadtree.get_contained_into(Rect::new(0.0, 0.0, 20.0, 20.0), &mut buffer);
+        assert_eq!(buffer, vec![&1]);
+
+        quadtree.get_overlapped_into(Rect::new(200.0, 200.0, 5.0, 5.0), &mut buffer);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn get_overlapped_after_one_insertion() {
+        let mut quadtree: Quadtree<i32> = Quadtree::default();
+        quadtree.insert(42, Rect::new(10.0, 10.0, 10.0, 10.0));
+
+        assert_eq!(
+            quadtree.get_overlapped(Rect::new(15.0, 10.0, 10.0, 10.0)),
+            vec![&42]
+        )
+    }
+
+    #[test]
+    fn get_two_overlapped_element_after_two_insertions() {
+        let mut quadtree: Quadtree<i32> = Quadtree::default();
+        quadtree.insert(42, Rect::new(10.0, 10.0, 10.0, 10.0));
+        quadtree.insert(5, Rect::new(15.0, 14.0, 15.0, 2.0));
+
+        let elements = quadtree.get_overlapped(Rect::new(10.0, 10.0, 10.0, 10.0));
+
+        assert!(elements.contains(&&42));
+        assert!(elements.contains(&&5));
+    }
+
+    #[test]
+    fn get_only_one_overlapped_element_after_two_insertions() {
+        let mut quadtree: Quadtree<i32> = Quadtree::default();
+        quadtree.insert(42, Rect::new(10.0, 10.0, 10.0, 10.0));
+        quadtree.insert(5, Rect::new(35.0, 10.0, 10.0, 10.0));
+
+        assert_eq!(
+            quadtree.get_overlapped(Rect::new(10.0, 10.0, 10.0, 10.0)),
+            vec![&42]
+        )
+    }
+
+    // Extremums
+    #[test]
+    fn topmost_and_leftmost_in_region() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 2);
+        let top = quadtree.insert(1, Rect::new(30.0, 5.0, 5.0, 5.0));
+        let left = quadtree.insert(2, Rect::new(10.0, 20.0, 5.0, 5.0));
+        quadtree.insert(3, Rect::new(40.0, 40.0, 5.0, 5.0));
+        quadtree.insert(4, Rect::new(1.0, 1.0, 1.0, 1.0));
+
+        let region = Rect::new(5.0, 3.0, 50.0, 50.0);
+
+        assert_eq!(quadtree.topmost_in_region(region), Some((top, &1)));
+        assert_eq!(quadtree.leftmost_in_region(region), Some((left, &2)));
+        assert_eq!(
+            quadtree.topmost_in_region(Rect::new(80.0, 80.0, 5.0, 5.0)),
+            None
+        );
+    }
+
+    // Coverage
+    #[test]
+    fn coverage_counts_overlaps_once() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 1);
+        quadtree.insert(1, Rect::new(0.0, 0.0, 10.0, 10.0));
+        quadtree.insert(2, Rect::new(5.0, 5.0, 10.0, 10.0));
+        quadtree.insert(3, Rect::new(50.0, 50.0, 10.0, 10.0));
+
+        assert_eq!(quadtree.coverage(Rect::new(0.0, 0.0, 20.0, 20.0)), 175.0);
+        assert_eq!(quadtree.coverage(Rect::new(55.0, 0.0, 45.0, 100.0)), 50.0);
+        assert_eq!(quadtree.coverage(Rect::new(20.0, 20.0, 10.0, 10.0)), 0.0);
+    }
+
+    // Early exit
+    #[test]
+    fn any_overlapped() {
+        let mut quadtree = Quadtree::default();
+        quadtree.insert(42, Rect::new(10.0, 10.0, 10.0, 10.0));
+
+        assert!(quadtree.any_overlapped(Rect::new(15.0, 15.0, 1.0, 1.0)));
+        assert!(!quadtree.any_overlapped(Rect::new(-50.0, -50.0, 10.0, 10.0)));
+    }
+
+    #[test]
+    fn find_overlapped_stops_at_first_match() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 2);
+        for i in 0..10 {
+            quadtree.insert(i, Rect::new(i as f32 * 5.0, 10.0, 2.0, 2.0));
+        }
+
+        let mut visited = 0;
+        let found = quadtree.find_overlapped(Rect::new(0.0, 0.0, 100.0, 100.0), |_, element| {
+            visited += 1;
+            *element == 7
+        });
+
+        assert_eq!(found.map(|(_, element)| *element), Some(7));
+        assert!(visited <= 10);
+        assert_eq!(
+            quadtree.find_overlapped(Rect::new(0.0, 0.0, 100.0, 100.0), |_, element| *element
+                == 42),
+            None
+        );
+    }
+
+    // Level of detail
+    #[test]
+    fn overlapped_nodes_stop_at_max_depth() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 1);
+        quadtree.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+        quadtree.insert(2, Rect::new(30.0, 30.0, 5.0, 5.0));
+        quadtree.insert(3, Rect::new(80.0, 80.0, 5.0, 5.0));
+
+        let root_only = quadtree.get_overlapped_nodes(Rect::new(0.0, 0.0, 100.0, 100.0), 0);
+        assert_eq!(root_only.len(), 1);
+        assert_eq!(root_only[0].size(), 3);
+
+        let first_level = quadtree.get_overlapped_nodes(Rect::new(0.0, 0.0, 40.0, 40.0), 1);
+        assert_eq!(first_level.len(), 1);
+        assert_eq!(first_level[0].depth(), 1);
+        assert_eq!(first_level[0].size(), 2);
+
+        let deep = quadtree.get_overlapped_nodes(Rect::new(0.0, 0.0, 20.0, 20.0), 10);
+        assert!(deep.iter().all(|node| node.is_leaf()));
+        assert_eq!(deep.iter().map(|node| node.size()).sum::<usize>(), 1);
+    }
+
+    #[test]
+    fn overlapped_grouped_by_node() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 2);
+        quadtree.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+        quadtree.insert(2, Rect::new(20.0, 20.0, 5.0, 5.0));
+        quadtree.insert(3, Rect::new(60.0, 10.0, 5.0, 5.0));
+        quadtree.insert(4, Rect::new(45.0, 10.0, 10.0, 5.0));
+        quadtree.insert(5, Rect::new(80.0, 80.0, 5.0, 5.0));
+
+        let mut groups = quadtree.overlapped_grouped(Rect::new(0.0, 0.0, 100.0, 40.0));
+        groups.sort_by(|a, b| a.0.w.total_cmp(&b.0.w).then(a.0.x.total_cmp(&b.0.x)));
+        for (_, elements) in groups.iter_mut() {
+            elements.sort();
+        }
+
+        assert_eq!(
+            groups,
+            vec![
+                (Rect::new(0.0, 0.0, 50.0, 50.0), vec![&1, &2]),
+                (Rect::new(50.0, 0.0, 50.0, 50.0), vec![&3]),
+                (Rect::new(0.0, 0.0, 100.0, 100.0), vec![&4]),
+            ]
+        );
+    }
+
+    #[test]
+    fn coarse_overlapped_counts_per_node() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 1);
+        quadtree.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+        quadtree.insert(2, Rect::new(30.0, 30.0, 5.0, 5.0));
+        quadtree.insert(3, Rect::new(80.0, 80.0, 5.0, 5.0));
+
+        let mut coarse = quadtree.coarse_overlapped(Rect::new(0.0, 0.0, 100.0, 40.0), 1);
+        coarse.sort_by(|a, b| a.0.x.total_cmp(&b.0.x));
+
+        assert_eq!(
+            coarse,
+            vec![
+                (Rect::new(0.0, 0.0, 50.0, 50.0), 2),
+                (Rect::new(50.0, 0.0, 50.0, 50.0), 0),
+            ]
+        );
+    }
+
+    // Query builder
+    #[test]
+    fn query_without_constraints_returns_everything() {
+        let mut quadtree = Quadtree::default();
+        quadtree.insert(1, Rect::new(10.0, 10.0, 10.0, 10.0));
+        quadtree.insert(2, Rect::new(-50.0, 20.0, 10.0, 10.0));
+
+        let mut elements = quadtree.query().collect();
+        elements.sort();
+
+        assert_eq!(elements, vec![&1, &2]);
+    }
+
+    #[test]
+    fn query_with_region_filter_and_limit() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 2);
+        for i in 0..10 {
+            quadtree.insert(i, Rect::new(i as f32 * 5.0, 10.0, 2.0, 2.0));
+        }
+        quadtree.insert(100, Rect::new(80.0, 80.0, 2.0, 2.0));
+
+        let mut elements = quadtree
+            .query()
+            .region(Rect::new(0.0, 0.0, 50.0, 50.0))
+            .filter(|element| element % 2 == 0)
+            .collect();
+        elements.sort();
+
+        assert_eq!(elements, vec![&0, &2, &4, &6, &8]);
+
+        let limited = quadtree
+            .query()
+            .region(Rect::new(0.0, 0.0, 50.0, 50.0))
+            .filter(|element| element % 2 == 0)
+            .limit(2)
+            .collect();
+
+        assert_eq!(limited.len(), 2);
+        assert!(limited.iter().all(|element| *element % 2 == 0));
+    }
+
+    #[test]
+    fn query_with_zero_limit_visits_nothing() {
+        let mut quadtree = Quadtree::default();
+        quadtree.insert(1, Rect::new(10.0, 10.0, 10.0, 10.0));
+
+        let mut visited = 0;
+        quadtree.query().limit(0).for_each(|_| visited += 1);
+
+        assert_eq!(visited, 0);
+    }
+
+    // Nearest
+    #[test]
+    fn nearest_to_rect_in_empty() {
+        let quadtree: Quadtree<i32> = Quadtree::default();
+        assert_eq!(
+            quadtree.nearest_to_rect(Rect::new(0.0, 0.0, 1.0, 1.0)),
+            None
+        );
+    }
+
+    #[test]
+    fn nearest_by_manhattan() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 1);
+        let diagonal = quadtree.insert(1, Rect::new(57.0, 57.0, 1.0, 1.0));
+        let straight = quadtree.insert(2, Rect::new(60.0, 50.0, 1.0, 1.0));
+        quadtree.insert(3, Rect::new(5.0, 5.0, 1.0, 1.0));
+
+        let manhattan = |region: &Rect, (px, py): (f32, f32)| {
+            let (cx, cy) = region.closest_point(px, py);
+            (px - cx).abs() + (py - cy).abs()
+        };
+        let euclidean = |region: &Rect, (px, py): (f32, f32)| region.distance_to_point(px, py);
+
+        assert_eq!(
+            quadtree.nearest_by((50.0, 50.0), euclidean),
+            Some((diagonal, &1))
+        );
+        assert_eq!(
+            quadtree.nearest_by((50.0, 50.0), manhattan),
+            Some((straight, &2))
+        );
+    }
+
+    #[test]
+    fn nearest_to_rect_finds_closest_element() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 2);
+        quadtree.insert(1, Rect::new(5.0, 5.0, 5.0, 5.0));
+        let closest = quadtree.insert(2, Rect::new(60.0, 60.0, 5.0, 5.0));
+        quadtree.insert(3, Rect::new(90.0, 10.0, 5.0, 5.0));
+        quadtree.insert(4, Rect::new(10.0, 90.0, 5.0, 5.0));
+
+        assert_eq!(
+            quadtree.nearest_to_rect(Rect::new(70.0, 70.0, 5.0, 5.0)),
+            Some((closest, &2))
+        );
+    }
+
+    #[test]
+    fn nearest_to_rect_overlapping_element() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 2);
+        quadtree.insert(1, Rect::new(5.0, 5.0, 5.0, 5.0));
+        quadtree.insert(2, Rect::new(60.0, 60.0, 5.0, 5.0));
+        let overlapping = quadtree.insert(3, Rect::new(40.0, 40.0, 20.0, 20.0));
+
+        assert_eq!(
+            quadtree.nearest_to_rect(Rect::new(55.0, 55.0, 1.0, 1.0)),
+            Some((overlapping, &3))
+        );
+    }
+
+    // Raycast
+    #[test]
+    fn raycast_returns_hits_front_to_back() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 1);
+        let far = quadtree.insert(3, Rect::new(80.0, 40.0, 5.0, 20.0));
+        let near = quadtree.insert(1, Rect::new(20.0, 45.0, 5.0, 10.0));
+        let middle = quadtree.insert(2, Rect::new(45.0, 45.0, 10.0, 10.0));
+        quadtree.insert(4, Rect::new(50.0, 10.0, 5.0, 5.0));
+
+        assert_eq!(
+            quadtree.raycast((0.0, 50.0), (1.0, 0.0)),
+            vec![(near, &1, 20.0), (middle, &2, 45.0), (far, &3, 80.0)]
+        );
+    }
+
+    #[test]
+    fn raycast_first_hit() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 1);
+        quadtree.insert(3, Rect::new(80.0, 40.0, 5.0, 20.0));
+        quadtree.insert(2, Rect::new(45.0, 45.0, 10.0, 10.0));
+        let near = quadtree.insert(1, Rect::new(20.0, 45.0, 5.0, 10.0));
+        quadtree.insert(4, Rect::new(50.0, 10.0, 5.0, 5.0));
+
+        assert_eq!(
+            quadtree.raycast_first((0.0, 50.0), (1.0, 0.0)),
+            Some((near, &1, 20.0))
+        );
+        assert_eq!(quadtree.raycast_first((0.0, 50.0), (-1.0, 0.0)), None);
+        assert_eq!(
+            quadtree
+                .raycast_first((100.0, 12.0), (-1.0, 0.0))
+                .map(|hit| hit.1),
+            Some(&4)
+        );
+    }
+
+    #[test]
+    fn raycast_missing_everything() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 1);
+        quadtree.insert(1, Rect::new(20.0, 45.0, 5.0, 10.0));
+
+        assert!(quadtree.raycast((0.0, 50.0), (-1.0, 0.0)).is_empty());
+        assert!(quadtree.raycast((0.0, 150.0), (1.0, 0.0)).is_empty());
+    }
+
+    #[test]
+    fn mutate_all_overlapped_elements() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 2);
+        for i in 0..10 {
+            quadtree.insert(i, Rect::new(i as f32 * 5.0, 10.0, 2.0, 2.0));
+        }
+
+        let elements = quadtree.get_overlapped_mut(Rect::new(0.0, 0.0, 21.0, 50.0));
+        assert_eq!(elements.len(), 5);
+        for element in elements {
+            *element *= -1;
+        }
+
+        let mut values: Vec<i32> = quadtree.query().collect().into_iter().copied().collect();
+        values.sort();
+
+        assert_eq!(values, vec![-4, -3, -2, -1, 0, 5, 6, 7, 8, 9]);
+    }
+
+    #[test]
+    fn ordered_queries_sort_by_id() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 2);
+        for i in 0..20 {
+            quadtree.insert(
+                i,
+                Rect::new((i * 37 % 90) as f32, (i * 53 % 90) as f32, 5.0, 5.0),
+            );
+        }
+
+        let everything = Rect::new(0.0, 0.0, 100.0, 100.0);
+        let expected: Vec<i32> = (0..20).collect();
+
+        let contained: Vec<i32> = quadtree
+            .get_contained_ordered(everything)
+            .into_iter()
+            .copied()
+            .collect();
+        let overlapped: Vec<i32> = quadtree
+            .get_overlapped_ordered(everything)
+            .into_iter()
+            .copied()
+            .collect();
+
+        assert_eq!(contained, expected);
+        assert_eq!(overlapped, expected);
+    }
+
+    #[test]
+    fn get_overlapped_sorted_by_distance() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 2);
+        let far = quadtree.insert(3, Rect::new(40.0, 40.0, 5.0, 5.0));
+        let near = quadtree.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+        let middle = quadtree.insert(2, Rect::new(20.0, 0.0, 5.0, 5.0));
+        let inside = quadtree.insert(0, Rect::new(0.0, 0.0, 5.0, 5.0));
+        quadtree.insert(4, Rect::new(80.0, 80.0, 5.0, 5.0));
+
+        assert_eq!(
+            quadtree.get_overlapped_sorted(Rect::new(0.0, 0.0, 50.0, 50.0), (0.0, 0.0)),
+            vec![(inside, &0), (near, &1), (middle, &2), (far, &3)]
+        );
+    }
+
+    #[test]
+    fn get_overlapped_ranked_by_area() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 2);
+        let small = quadtree.insert(1, Rect::new(18.0, 18.0, 4.0, 4.0));
+        let large = quadtree.insert(2, Rect::new(0.0, 0.0, 15.0, 15.0));
+        let tie_a = quadtree.insert(3, Rect::new(25.0, 0.0, 5.0, 2.0));
+        let tie_b = quadtree.insert(4, Rect::new(10.0, 25.0, 2.0, 5.0));
+        quadtree.insert(5, Rect::new(80.0, 80.0, 5.0, 5.0));
+
+        assert_eq!(
+            quadtree.get_overlapped_ranked(Rect::new(5.0, 0.0, 25.0, 30.0)),
+            vec![
+                (large, &2, 150.0),
+                (small, &1, 16.0),
+                (tie_a, &3, 10.0),
+                (tie_b, &4, 10.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn closest_pair_among_scattered_rects() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 2);
+        quadtree.insert(1, Rect::new(5.0, 5.0, 5.0, 5.0));
+        quadtree.insert(2, Rect::new(30.0, 5.0, 5.0, 5.0));
+        let a = quadtree.insert(3, Rect::new(60.0, 60.0, 5.0, 5.0));
+        let b = quadtree.insert(4, Rect::new(68.0, 64.0, 5.0, 5.0));
+        quadtree.insert(5, Rect::new(5.0, 80.0, 5.0, 5.0));
+        quadtree.insert(6, Rect::new(90.0, 10.0, 5.0, 5.0));
+
+        assert_eq!(quadtree.closest_pair(), Some((a, b, 3.0)));
+    }
+
+    #[test]
+    fn closest_pair_matches_brute_force() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 2);
+        for i in 0..60u32 {
+            let x = (i * 37 % 97) as f32;
+            let y = (i * 61 % 89) as f32;
+            quadtree.insert(i, Rect::new(x, y, 1.0 + (i % 3) as f32, 1.0));
+        }
+
+        let elements: Vec<(u64, Rect)> = quadtree.iter().map(|(id, _, r)| (id, r)).collect();
+        let mut expected: Option<(u64, u64, f32)> = None;
+        for (a, region_a) in &elements {
+            for (b, region_b) in &elements {
+                if a >= b {
+                    continue;
+                }
+                let distance = region_a.distance_to_rect(region_b);
+                let is_better = match expected {
+                    Some((x, y, best)) => {
+                        distance < best || (distance == best && (*a, *b) < (x, y))
+                    }
+                    None => true,
+                };
+                if is_better {
+                    expected = Some((*a, *b, distance));
+                }
+            }
+        }
+
+        assert_eq!(quadtree.closest_pair(), expected);
+    }
+
+    #[test]
+    fn closest_pair_needs_two_elements() {
+        let mut quadtree = Quadtree::default();
+        assert_eq!(quadtree.closest_pair(), None);
+
+        quadtree.insert(1, Rect::new(5.0, 5.0, 5.0, 5.0));
+        assert_eq!(quadtree.closest_pair(), None);
+    }
+
+    #[test]
+    fn get_in_polygon_view_cone() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 1);
+        quadtree.insert(1, Rect::new(20.0, 8.0, 4.0, 4.0));
+        quadtree.insert(2, Rect::new(60.0, 40.0, 4.0, 4.0));
+        quadtree.insert(3, Rect::new(10.0, 60.0, 4.0, 4.0));
+        quadtree.insert(4, Rect::new(90.0, 90.0, 4.0, 4.0));
+
+        let cone = [(0.0, 10.0), (80.0, 0.0), (80.0, 60.0)];
+        let mut elements = quadtree.get_in_polygon(&cone);
+        elements.sort();
+
+        assert_eq!(elements, vec![&1, &2]);
+        assert!(quadtree.get_in_polygon(&cone[..2]).is_empty());
+    }
+
+    #[test]
+    fn get_in_quad_trapezoid_frustum() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 1);
+        quadtree.insert(1, Rect::new(48.0, 85.0, 4.0, 4.0));
+        quadtree.insert(2, Rect::new(10.0, 20.0, 4.0, 4.0));
+        quadtree.insert(3, Rect::new(5.0, 85.0, 4.0, 4.0));
+
+        let frustum = [(40.0, 90.0), (60.0, 90.0), (95.0, 10.0), (5.0, 10.0)];
+        let mut elements = quadtree.get_in_quad(frustum);
+        elements.sort();
+
+        assert_eq!(elements, vec![&1, &2]);
+    }
+
+    // Removing
+    #[test]
+    fn remove_one_element() {
+        let mut quadtree: Quadtree<i32> = Quadtree::default();
+        let value = 42;
+        let region = Rect::new(10.0, 10.0, 10.0, 10.0);
+        let id = quadtree.insert(value, region);
+
+        assert_eq!(quadtree.remove(id).unwrap(), (value, region));
+    }
+
+    #[test]
+    fn find_ids_by_value() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 1);
+        let first = quadtree.insert(7, Rect::new(10.0, 10.0, 5.0, 5.0));
+        let other = quadtree.insert(8, Rect::new(30.0, 30.0, 5.0, 5.0));
+        let second = quadtree.insert(7, Rect::new(60.0, 60.0, 5.0, 5.0));
+
+        assert_eq!(quadtree.find_id_of(&8), Some(other));
+        assert_eq!(quadtree.find_id_of(&9), None);
+        assert!([first, second].contains(&quadtree.find_id_of(&7).unwrap()));
+        assert_eq!(quadtree.find_all_ids_of(&7), vec![first, second]);
+    }
+
+    #[test]
+    fn remove_by_value() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 1);
+        quadtree.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+        let id = quadtree.insert(2, Rect::new(60.0, 60.0, 5.0, 5.0));
+
+        assert_eq!(
+            quadtree.remove_value(&2),
+            Some((id, 2, Rect::new(60.0, 60.0, 5.0, 5.0)))
+        );
+        assert_eq!(quadtree.remove_value(&2), None);
+        assert!(quadtree.contains(&1));
+        assert_eq!(quadtree.root.size(), 1);
+    }
+
+    #[test]
+    fn fuse_threshold_keeps_oscillating_node_subdivided() {
+        let region = Rect::new(0.0, 0.0, 100.0, 100.0);
+        let mut eager = Quadtree::new(region, 4);
+        let mut lazy = Quadtree::with_fuse_threshold(region, 4, 2);
+        for quadtree in [&mut eager, &mut lazy] {
+            for i in 0..4 {
+                let x = (i % 2) as f32 * 50.0 + 10.0;
+                let y = (i / 2) as f32 * 50.0 + 10.0;
+                quadtree.insert(i, Rect::new(x, y, 5.0, 5.0));
+            }
+        }
+
+        let mut eager_fuses = 0;
+        let mut lazy_fuses = 0;
+        for _ in 0..10 {
+            let id = eager.insert(4, Rect::new(70.0, 70.0, 5.0, 5.0));
+            eager.remove(id);
+            eager_fuses += eager.root.is_leaf() as usize;
+
+            let id = lazy.insert(4, Rect::new(70.0, 70.0, 5.0, 5.0));
+            lazy.remove(id);
+            lazy_fuses += lazy.root.is_leaf() as usize;
+        }
+
+        assert_eq!(eager_fuses, 10);
+        assert_eq!(lazy_fuses, 0);
+
+        lazy.remove_value(&0);
+        lazy.remove_value(&1);
+        assert!(lazy.root.is_leaf());
+        assert_eq!(lazy.fuse_threshold(), 2);
+        assert_eq!(eager.fuse_threshold(), 4);
+    }
+
+    #[test]
+    #[should_panic]
+    fn zero_node_capacity_panics() {
+        let _: Quadtree<i32> = Quadtree::new(Rect::new(0.0, 0.0, 1.0, 1.0), 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn set_zero_node_capacity_panics() {
+        let mut quadtree = Quadtree::default();
+        quadtree.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+        quadtree.set_max_node_capacity(0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn builder_zero_node_capacity_panics() {
+        let _: Quadtree<i32> = QuadtreeBuilder::new().max_node_capacity(0).build();
+    }
+
+    #[test]
+    #[should_panic]
+    fn fuse_threshold_above_capacity_panics() {
+        let _: Quadtree<i32> = Quadtree::with_fuse_threshold(Rect::new(0.0, 0.0, 1.0, 1.0), 2, 3);
+    }
+
+    #[test]
+    fn remove_many_matches_looped_remove() {
+        let region = Rect::new(0.0, 0.0, 100.0, 100.0);
+        let mut batched = Quadtree::new(region, 2);
+        let mut looped = Quadtree::new(region, 2);
+        let mut ids = Vec::new();
+        for i in 0..16 {
+            let x = (i % 4) as f32 * 25.0 + 5.0;
+            let y = (i / 4) as f32 * 25.0 + 5.0;
+            ids.push(batched.insert(i, Rect::new(x, y, 5.0, 5.0)));
+            looped.insert(i, Rect::new(x, y, 5.0, 5.0));
+        }
+
+        let to_remove = [ids[5], 999, ids[0], ids[1], ids[4], ids[0], ids[15]];
+        let removed = batched.remove_many(&to_remove);
+        for id in to_remove {
+            looped.remove(id);
+        }
+
+        let values: Vec<i32> = removed.iter().map(|(value, _)| *value).collect();
+        assert_eq!(values, vec![5, 0, 1, 4, 15]);
+        assert_eq!(batched.validate(), Ok(()));
+        assert_eq!(batched.nodes().count(), looped.nodes().count());
+        assert!(batched == looped);
+    }
+
+    #[test]
+    fn remove_many_only_fuses_along_removal_paths() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 1);
+        for i in 0..64 {
+            let x = (i % 8) as f32 * 12.5 + 1.0;
+            let y = (i / 8) as f32 * 12.5 + 1.0;
+            quadtree.insert(i, Rect::new(x, y, 2.0, 2.0));
+        }
+        let id = 27;
+        let depth = quadtree.depth_of(id).unwrap() as usize;
+
+        let region = quadtree.elements.remove(&id).unwrap().1;
+        quadtree.root.remove_unfused(id, region);
+        let visited = quadtree
+            .root
+            .fuse_along(&[region], quadtree.fuse_threshold());
+
+        assert_eq!(visited, depth + 1);
+        assert!(visited < quadtree.nodes().count());
+        assert_eq!(quadtree.validate(), Ok(()));
+    }
+
+    #[test]
+    fn deduplicate_keeps_lowest_id() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 2);
+        let first = quadtree.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+        quadtree.insert(2, Rect::new(10.0, 10.0, 5.0, 5.0));
+        quadtree.insert(3, Rect::new(10.005, 9.995, 5.0, 5.001));
+        let distinct = quadtree.insert(4, Rect::new(10.0, 10.0, 6.0, 5.0));
+        let far = quadtree.insert(5, Rect::new(60.0, 60.0, 5.0, 5.0));
+
+        assert_eq!(quadtree.deduplicate(0.01), 2);
+
+        let mut ids: Vec<u64> = quadtree.iter().map(|(id, _, _)| id).collect();
+        ids.sort();
+        assert_eq!(ids, vec![first, distinct, far]);
+        assert_eq!(quadtree.validate(), Ok(()));
+    }
+
+    #[test]
+    fn remove_in_region_clears_quadrant() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 2);
+        for i in 0..16 {
+            let x = (i % 4) as f32 * 25.0 + 5.0;
+            let y = (i / 4) as f32 * 25.0 + 5.0;
+            quadtree.insert(i, Rect::new(x, y, 5.0, 5.0));
+        }
+
+        let removed = quadtree.remove_in_region(Rect::new(0.0, 0.0, 50.0, 50.0));
+        let mut removed_values: Vec<i32> = removed.iter().map(|(_, value, _)| *value).collect();
+        removed_values.sort();
+
+        assert_eq!(removed_values, vec![0, 1, 4, 5]);
+        assert_eq!(quadtree.size(), 12);
+        assert_eq!(quadtree.root.size(), 12);
+        assert!(quadtree
+            .get_overlapped(Rect::new(0.0, 0.0, 49.0, 49.0))
+            .is_empty());
+        assert_eq!(
+            quadtree
+                .get_contained(Rect::new(50.0, 0.0, 50.0, 100.0))
+                .len(),
+            8
+        );
+
+        for node in quadtree.nodes() {
+            if node.is_leaf() {
+                assert!(node.elements().len() <= 2);
+            }
+        }
+    }
+
+    #[test]
+    fn remove_in_region_everything_fuses_root() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 2);
+        for i in 0..16 {
+            let x = (i % 4) as f32 * 25.0 + 5.0;
+            let y = (i / 4) as f32 * 25.0 + 5.0;
+            quadtree.insert(i, Rect::new(x, y, 5.0, 5.0));
+        }
+
+        assert_eq!(
+            quadtree
+                .remove_in_region(Rect::new(0.0, 0.0, 100.0, 100.0))
+                .len(),
+            16
+        );
+        assert!(quadtree.is_empty());
+        assert!(quadtree.root.is_leaf());
+        assert_eq!(quadtree.root.size(), 0);
+    }
+
+    #[test]
+    fn shrink_to_fit_after_removals() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 1000);
+        let ids: Vec<u64> = (0..1000)
+            .map(|i| quadtree.insert(i, Rect::new(10.0, 10.0, 5.0, 5.0)))
+            .collect();
+        for id in &ids[..990] {
+            quadtree.remove(*id);
+        }
+
+        let capacity = quadtree.capacity();
+        let root_capacity = quadtree.root.elements.capacity();
+        assert!(capacity >= 1000);
+
+        quadtree.shrink_to_fit();
+
+        assert!(quadtree.capacity() < capacity);
+        assert!(quadtree.capacity() >= quadtree.size());
+        assert!(quadtree.root.elements.capacity() < root_capacity);
+        assert_eq!(quadtree.size(), 10);
+    }
+
+    #[test]
+    fn memory_usage_grows_with_tree() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 2);
+        let empty = quadtree.memory_usage();
+        assert!(empty >= mem::size_of::<Quadtree<i32>>());
+
+        for i in 0..100 {
+            quadtree.insert(
+                i,
+                Rect::new((i % 10) as f32 * 10.0, (i / 10) as f32 * 10.0, 1.0, 1.0),
+            );
+        }
+
+        let full = quadtree.memory_usage();
+        assert!(full > empty);
+        assert!(full >= quadtree.nodes().count() * mem::size_of::<Node>());
+    }
+
+    // Entries
+    #[test]
+    fn entry() {
+        let mut quadtree = Quadtree::default();
+        let entry_id = quadtree.insert(42, Rect::new(10.0, 10.0, 10.0, 10.0));
+
+        let entry = quadtree.entry(entry_id);
+
+        assert_eq!(entry.value(), &42);
+        assert_eq!(entry.id(), entry_id);
+    }
+
+    #[test]
+    fn remove_through_entry() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 1);
+        quadtree.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+        quadtree.insert(2, Rect::new(60.0, 60.0, 5.0, 5.0));
+
+        let entry = quadtree.entry_mut_at_point((62.0, 62.0)).unwrap();
+
+        assert_eq!(entry.remove(), (2, Rect::new(60.0, 60.0, 5.0, 5.0)));
+        assert!(!quadtree.contains(&2));
+        assert_eq!(quadtree.validate(), Ok(()));
+    }
+
+    #[test]
+    fn entries_in_region_then_remove() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 1);
+        quadtree.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+        quadtree.insert(2, Rect::new(20.0, 20.0, 5.0, 5.0));
+        quadtree.insert(3, Rect::new(80.0, 80.0, 5.0, 5.0));
+
+        let odd: Vec<u64> = quadtree
+            .entries_in_region(Rect::new(0.0, 0.0, 50.0, 50.0))
+            .filter(|entry| entry.value() % 2 == 1)
+            .map(|entry| entry.id())
+            .collect();
+        for id in odd {
+            quadtree.remove(id);
+        }
+
+        let mut left: Vec<&i32> = quadtree.iter().map(|(_, value, _)| value).collect();
+        left.sort();
+        assert_eq!(left, vec![&2, &3]);
+    }
+
+    #[test]
+    fn move_entry() {
+        let mut quadtree = Quadtree::default();
+        let entry_id = quadtree.insert(42, Rect::new(10.0, 10.0, 10.0, 10.0));
+
+        let mut entry = quadtree.entry_mut(entry_id);
+        entry.move_entry(Rect::new(20.0, 20.0, 5.0, 5.0));
+
+        assert_eq!(
+            quadtree.get_contained(Rect::new(20.0, 20.0, 5.0, 5.0)),
+            vec![&42]
+        );
+    }
+
+    #[test]
+    fn move_by_accumulates() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 1);
+        let id = quadtree.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+        quadtree.insert(2, Rect::new(80.0, 80.0, 5.0, 5.0));
+
+        let mut entry = quadtree.entry_mut(id);
+        for _ in 0..4 {
+            entry.move_by(10.0, 5.0);
+        }
+
+        assert_eq!(entry.region(), Rect::new(50.0, 30.0, 5.0, 5.0));
+        assert_eq!(
+            quadtree.get_contained(Rect::new(50.0, 30.0, 5.0, 5.0)),
+            vec![&1]
+        );
+        assert!(quadtree
+            .get_overlapped(Rect::new(0.0, 0.0, 40.0, 40.0))
+            .is_empty());
+    }
+
+    #[test]
+    fn reinsert_by_id() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 1);
+        let id = quadtree.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+        quadtree.insert(2, Rect::new(60.0, 60.0, 5.0, 5.0));
+
+        assert!(quadtree.reinsert(id, Rect::new(70.0, 20.0, 5.0, 5.0)));
+        assert!(quadtree.reinsert(id, Rect::new(80.0, 30.0, 5.0, 5.0)));
+
+        assert_eq!(quadtree.entry(id).region(), Rect::new(80.0, 30.0, 5.0, 5.0));
+        assert_eq!(
+            quadtree.get_contained(Rect::new(80.0, 30.0, 5.0, 5.0)),
+            vec![&1]
+        );
+        assert!(quadtree
+            .get_overlapped(Rect::new(0.0, 0.0, 30.0, 30.0))
+            .is_empty());
+        assert_eq!(quadtree.root.size(), 2);
+    }
+
+    #[test]
+    fn reinsert_unknown_id() {
+        let mut quadtree: Quadtree<i32> = Quadtree::default();
+        assert!(!quadtree.reinsert(42, Rect::new(10.0, 10.0, 5.0, 5.0)));
+    }
+
+    #[test]
+    fn swap_positions_of_two_elements() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 2);
+        let region_a = Rect::new(10.0, 10.0, 5.0, 5.0);
+        let region_b = Rect::new(70.0, 60.0, 10.0, 10.0);
+        let a = quadtree.insert(1, region_a);
+        let b = quadtree.insert(2, region_b);
+        quadtree.insert(3, Rect::new(80.0, 10.0, 5.0, 5.0));
+
+        assert!(quadtree.swap_positions(a, b));
+
+        assert_eq!(quadtree.entry(a).region(), region_b);
+        assert_eq!(quadtree.entry(b).region(), region_a);
+        assert_eq!(quadtree.get_contained(region_a), vec![&2]);
+        assert_eq!(quadtree.get_contained(region_b), vec![&1]);
+        assert_eq!(quadtree.root.size(), 3);
+    }
+
+    #[test]
+    fn swap_positions_with_unknown_id() {
+        let mut quadtree = Quadtree::default();
+        let region = Rect::new(10.0, 10.0, 5.0, 5.0);
+        let a = quadtree.insert(1, region);
+
+        assert!(!quadtree.swap_positions(a, 42));
+        assert_eq!(quadtree.entry(a).region(), region);
+    }
+
+    #[test]
+    fn changing_max_node_capacity_rebuilds() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 1);
+        for i in 0..8 {
+            quadtree.insert(i, Rect::new(i as f32 * 10.0, i as f32 * 10.0, 5.0, 5.0));
+        }
+        assert!(quadtree.root.is_node());
+
+        quadtree.set_max_node_capacity(10);
+
+        assert_eq!(quadtree.max_node_capacity(), 10);
+        assert!(quadtree.root.is_leaf());
+        assert_eq!(quadtree.root.size(), 8);
+
+        let mut elements = quadtree.get_contained(Rect::new(0.0, 0.0, 40.0, 40.0));
+        elements.sort();
+        assert_eq!(elements, vec![&0, &1, &2, &3]);
+    }
+
+    #[test]
+    fn shrink_root_reduces_height() {
+        let mut quadtree = Quadtree::new(Rect::new(-10000.0, -10000.0, 20000.0, 20000.0), 2);
+        for i in 0..10 {
+            quadtree.insert(
+                i,
+                Rect::new(i as f32 * 10.0, (i % 3) as f32 * 10.0, 5.0, 5.0),
+            );
+        }
+        let height = quadtree.height();
+
+        quadtree.shrink_root();
+
+        assert!(quadtree.height() < height);
+        assert_eq!(quadtree.root.region(), Rect::new(-0.95, -0.95, 96.9, 26.9));
+        let mut elements = quadtree.get_contained(Rect::new(0.0, 0.0, 30.0, 30.0));
+        elements.sort();
+        assert_eq!(elements, vec![&0, &1, &2]);
+    }
+
+    #[test]
+    fn bounding_box_of_elements() {
+        let mut quadtree = Quadtree::default();
+        assert_eq!(quadtree.bounding_box(), None);
+
+        quadtree.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+        quadtree.insert(2, Rect::new(-20.0, 30.0, 5.0, 5.0));
+
+        assert_eq!(
+            quadtree.bounding_box(),
+            Some(Rect::new(-20.0, 10.0, 35.0, 25.0))
+        );
+    }
+
+    // Splitting
+    #[test]
+    fn split_at_x_by_center() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 2);
+        let a = quadtree.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+        let b = quadtree.insert(2, Rect::new(40.0, 50.0, 20.0, 5.0));
+        let c = quadtree.insert(3, Rect::new(48.0, 80.0, 10.0, 5.0));
+        let d = quadtree.insert(4, Rect::new(90.0, 90.0, 5.0, 5.0));
+
+        let (mut left, mut right) = quadtree.split_at_x(50.0);
+
+        assert_eq!(left.size(), 1);
+        assert_eq!(right.size(), 3);
+        assert_eq!(left.entry(a).value(), &1);
+        assert_eq!(right.entry(b).value(), &2);
+        assert_eq!(right.entry(c).value(), &3);
+        assert_eq!(right.entry(d).value(), &4);
+        assert_eq!(left.root.region(), Rect::new(0.0, 0.0, 50.0, 100.0));
+        assert_eq!(right.root.region(), Rect::new(40.0, 0.0, 60.0, 100.0));
+    }
+
+    #[test]
+    fn split_at_y_by_center() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 2);
+        quadtree.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+        quadtree.insert(2, Rect::new(10.0, 45.0, 5.0, 20.0));
+        quadtree.insert(3, Rect::new(90.0, 90.0, 5.0, 5.0));
+
+        let (mut top, bottom) = quadtree.split_at_y(50.0);
+
+        assert_eq!(top.size(), 1);
+        assert_eq!(bottom.size(), 2);
+        assert_eq!(bottom.root.region(), Rect::new(0.0, 45.0, 100.0, 55.0));
+        assert_eq!(top.insert(4, Rect::new(0.0, 0.0, 1.0, 1.0)), 3);
+    }
+
+    // Mapping
+    #[test]
+    fn map_keeps_ids_and_structure() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 1);
+        let first = quadtree.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+        let second = quadtree.insert(2, Rect::new(60.0, 60.0, 5.0, 5.0));
+        let node_count = quadtree.nodes().count();
+
+        let mut mapped = quadtree.map(|value| value.to_string());
+
+        assert_eq!(mapped.entry(first).value(), "1");
+        assert_eq!(
+            mapped.entry(second).region(),
+            Rect::new(60.0, 60.0, 5.0, 5.0)
+        );
+        assert_eq!(mapped.nodes().count(), node_count);
+        assert_eq!(mapped.validate(), Ok(()));
+        assert!(mapped.insert("3".to_string(), Rect::new(1.0, 1.0, 1.0, 1.0)) > second);
+    }
+
+    // Merging
+    #[test]
+    fn copy_contained_chunk() {
+        let mut source = Quadtree::new(Rect::new(0.0, 0.0, 200.0, 100.0), 2);
+        source.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+        source.insert(2, Rect::new(60.0, 60.0, 5.0, 5.0));
+        source.insert(3, Rect::new(150.0, 10.0, 5.0, 5.0));
+        source.insert(4, Rect::new(95.0, 50.0, 10.0, 5.0));
+
+        let mut chunk = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 2);
+        let copied = chunk.insert_all_contained(&source, Rect::new(0.0, 0.0, 100.0, 100.0));
+
+        let mut values: Vec<&i32> = chunk.iter().map(|(_, value, _)| value).collect();
+        values.sort();
+        assert_eq!(copied, 2);
+        assert_eq!(values, vec![&1, &2]);
+        assert_eq!(source.size(), 4);
+    }
+
+    #[test]
+    fn merge_two_populated_trees() {
+        let mut quadtree = Quadtree::default();
+        let a = quadtree.insert(1, Rect::new(10.0, 10.0, 10.0, 10.0));
+        quadtree.insert(2, Rect::new(-50.0, -50.0, 10.0, 10.0));
+
+        let mut other = Quadtree::new(Rect::new(0.0, 0.0, 50.0, 50.0), 2);
+        let b = other.insert(3, Rect::new(10.0, 10.0, 5.0, 5.0));
+        let c = other.insert(4, Rect::new(30.0, 30.0, 5.0, 5.0));
+
+        let id_map = quadtree.merge(other);
+
+        assert_eq!(quadtree.size(), 4);
+        assert_eq!(quadtree.root.size(), 4);
+        assert_eq!(id_map.len(), 2);
+        assert_eq!(quadtree.entry(id_map[&b]).value(), &3);
+        assert_eq!(quadtree.entry(id_map[&c]).value(), &4);
+        assert_eq!(quadtree.entry(a).value(), &1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn merge_element_outside_root() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 50.0, 50.0), 2);
+
+        let mut other = Quadtree::default();
+        other.insert(1, Rect::new(-50.0, -50.0, 10.0, 10.0));
+
+        quadtree.merge(other);
+    }
+
+    // Equality
+    #[test]
+    fn equal_regardless_of_ids_and_structure() {
+        let mut a = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 1);
+        a.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+        a.insert(2, Rect::new(60.0, 60.0, 5.0, 5.0));
+
+        let mut b = Quadtree::default();
+        b.insert_with_id(42, 2, Rect::new(60.0, 60.0, 5.0, 5.0));
+        b.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+
+        assert!(a == b);
+    }
+
+    #[test]
+    fn not_equal_with_duplicate_values_in_different_regions() {
+        let mut a = Quadtree::default();
+        a.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+        a.insert(1, Rect::new(20.0, 20.0, 5.0, 5.0));
+
+        let mut b = Quadtree::default();
+        b.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+        b.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+
+        assert!(a != b);
+    }
+
+    // Node lookup
+    #[test]
+    fn node_at_point_finds_deepest_leaf() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 1);
+        quadtree.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+        quadtree.insert(2, Rect::new(30.0, 30.0, 5.0, 5.0));
+
+        let node = quadtree.node_at_point((12.0, 12.0)).unwrap();
+
+        assert!(node.is_leaf());
+        assert!(node.region().contains_point(12.0, 12.0));
+        assert_eq!(node.depth(), 2);
+        assert!(node.elements().contains_key(&0));
+    }
+
+    #[test]
+    fn node_at_point_outside_root() {
+        let quadtree: Quadtree<i32> = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 1);
+        assert!(quadtree.node_at_point((-1.0, 50.0)).is_none());
+    }
+
+    #[test]
+    fn walk_children_from_root() {
+        fn count(node: &Node) -> usize {
+            1 + node
+                .children()
+                .map_or(0, |children| children.iter().map(count).sum())
+        }
+
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 1);
+        quadtree.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+        quadtree.insert(2, Rect::new(30.0, 30.0, 5.0, 5.0));
+
+        let children = quadtree.root().children().unwrap();
+        assert_eq!(children[3].region(), Rect::new(50.0, 50.0, 50.0, 50.0));
+        assert!(children[3].children().is_none());
+        assert_eq!(count(quadtree.root()), quadtree.nodes().count());
+    }
+
+    #[test]
+    fn nodes_with_path_address_children() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 1);
+        quadtree.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+        quadtree.insert(2, Rect::new(30.0, 30.0, 5.0, 5.0));
+
+        let nodes: Vec<(Vec<u8>, &Node)> = quadtree.nodes_with_path().collect();
+
+        assert_eq!(nodes.len(), quadtree.nodes().count());
+        assert_eq!(nodes[0].0, Vec::<u8>::new());
+        for (path, node) in nodes.iter() {
+            assert_eq!(path.len() as u32, node.depth());
+        }
+
+        let (_, node) = nodes.iter().find(|(path, _)| path == &[0, 3]).unwrap();
+        assert_eq!(node.region(), Rect::new(25.0, 25.0, 25.0, 25.0));
+        assert!(node.elements().contains_key(&1));
+    }
+
+    #[test]
+    fn node_elements_resolve_values() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 1);
+        quadtree.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+        let id = quadtree.insert(2, Rect::new(60.0, 60.0, 5.0, 5.0));
+
+        let node = quadtree.node_at_point((62.0, 62.0)).unwrap();
+        let elements: Vec<_> = quadtree.node_elements(node).collect();
+
+        assert_eq!(elements, vec![(id, &2, Rect::new(60.0, 60.0, 5.0, 5.0))]);
+        assert_eq!(quadtree.node_elements(&quadtree.root).count(), 0);
+    }
+
+    // Folding
+    #[test]
+    fn fold_nodes_computes_center_of_mass() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 1);
+        quadtree.insert(1.0, Rect::new(9.0, 9.0, 2.0, 2.0));
+        quadtree.insert(3.0, Rect::new(89.0, 9.0, 2.0, 2.0));
+        quadtree.insert(2.0, Rect::new(49.0, 49.0, 2.0, 2.0));
+        quadtree.insert(2.0, Rect::new(29.0, 69.0, 2.0, 2.0));
+
+        let (mass, x, y) = quadtree.fold_nodes(
+            |_, elements| {
+                elements
+                    .iter()
+                    .fold((0.0, 0.0, 0.0), |(m, x, y), (_, mass, region)| {
+                        let (cx, cy) = region.center();
+                        (m + **mass, x + cx * **mass, y + cy * **mass)
+                    })
+            },
+            |_, own, children| {
+                children
+                    .into_iter()
+                    .fold(own, |(m, x, y), (cm, cx, cy)| (m + cm, x + cx, y + cy))
+            },
+        );
+
+        assert_eq!(mass, 8.0);
+        assert_eq!((x / mass, y / mass), (55.0, 35.0));
+    }
+
+    #[test]
+    fn fold_nodes_counts_nodes() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 1);
+        for i in 0..6 {
+            quadtree.insert(i, Rect::new(i as f32 * 15.0, i as f32 * 15.0, 2.0, 2.0));
+        }
+
+        let count = quadtree.fold_nodes(
+            |_, _| 1,
+            |_, own, children| own + children.iter().sum::<usize>(),
+        );
+
+        assert_eq!(count, quadtree.nodes().count());
+    }
+
+    // Occupancy grid
+    #[test]
+    fn occupancy_grid_counts_centers() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 2);
+        quadtree.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+        quadtree.insert(2, Rect::new(20.0, 20.0, 5.0, 5.0));
+        quadtree.insert(3, Rect::new(60.0, 10.0, 5.0, 5.0));
+        quadtree.insert(4, Rect::new(10.0, 80.0, 5.0, 5.0));
+
+        assert_eq!(quadtree.occupancy_grid(2, 2), vec![2, 1, 1, 0]);
+    }
+
+    #[test]
+    fn occupancy_grid_boundaries() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 2);
+        quadtree.insert(1, Rect::new(45.0, 0.0, 10.0, 0.0));
+        quadtree.insert(2, Rect::new(90.0, 80.0, 10.0, 20.0));
+
+        assert_eq!(quadtree.occupancy_grid(2, 1), vec![0, 2]);
+        assert!(quadtree.occupancy_grid(0, 3).is_empty());
+    }
+
+    #[test]
+    fn ascii_rendering() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 2);
+        quadtree.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+        quadtree.insert(2, Rect::new(60.0, 80.0, 5.0, 5.0));
+        quadtree.insert(3, Rect::new(90.0, 90.0, 5.0, 5.0));
+
+        assert_eq!(quadtree.to_ascii(4, 2), "#...\n..##\n");
+        assert_eq!(quadtree.to_ascii(0, 2), "");
+    }
+
+    // Scanline ordering
+    #[test]
+    fn iter_scanline_rows_then_columns() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 2);
+        let c = quadtree.insert(3, Rect::new(5.0, 50.0, 5.0, 5.0));
+        let b = quadtree.insert(2, Rect::new(80.0, 10.0, 5.0, 5.0));
+        let a = quadtree.insert(1, Rect::new(10.0, 10.0, 50.0, 5.0));
+        let d = quadtree.insert(4, Rect::new(5.0, 50.0, 1.0, 1.0));
+
+        let order: Vec<(u64, &i32)> = quadtree.iter_scanline().collect();
 
-    use super::*;
+        assert_eq!(order, vec![(a, &1), (b, &2), (c, &3), (d, &4)]);
+    }
 
+    // Morton ordering
     #[test]
-    fn create_empty() {
-        let quadtree: Quadtree<i32> = Quadtree::default();
+    fn entries_in_morton_order() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 2);
+        let bottom_right = quadtree.insert(4, Rect::new(80.0, 80.0, 5.0, 5.0));
+        let top_right = quadtree.insert(2, Rect::new(80.0, 10.0, 5.0, 5.0));
+        let bottom_left = quadtree.insert(3, Rect::new(10.0, 80.0, 5.0, 5.0));
+        let top_left = quadtree.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
 
-        assert!(quadtree.is_empty());
-        assert_eq!(quadtree.size(), 0);
-        assert!(quadtree.root.is_leaf());
+        assert_eq!(
+            quadtree.entries_morton(),
+            vec![
+                (top_left, &1),
+                (top_right, &2),
+                (bottom_left, &3),
+                (bottom_right, &4)
+            ]
+        );
     }
 
-    // Insertion
+    // Validation
     #[test]
-    fn insert_one_element() {
-        let mut quadtree = Quadtree::default();
-        quadtree.insert(42, Rect::new(10.0, 10.0, 10.0, 10.0));
+    fn validate_after_mutations() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 2);
+        let mut ids = Vec::new();
+        for i in 0..20 {
+            let x = (i * 37 % 90) as f32;
+            let y = (i * 53 % 90) as f32;
+            ids.push(quadtree.insert(i, Rect::new(x, y, 5.0, 5.0)));
+        }
+        assert_eq!(quadtree.validate(), Ok(()));
 
-        assert!(quadtree.contains(&42));
-        assert_eq!(quadtree.size(), 1);
-        assert!(!quadtree.is_empty());
+        for id in ids.iter().step_by(3) {
+            quadtree.entry_mut(*id).move_by(3.0, 2.0);
+        }
+        for id in ids.iter().step_by(4) {
+            quadtree.remove(*id);
+        }
+        quadtree.remove_in_region(Rect::new(0.0, 0.0, 30.0, 30.0));
+
+        assert_eq!(quadtree.validate(), Ok(()));
     }
 
     #[test]
-    fn insert_two_elements() {
-        let mut quadtree = Quadtree::default();
-        quadtree.insert(42, Rect::new(10.0, 10.0, 10.0, 10.0));
-        quadtree.insert(5, Rect::new(50.0, 20.0, 40.0, 10.0));
+    fn validate_detects_wrong_size() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 2);
+        quadtree.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+        quadtree.root.size = 2;
 
-        assert!(quadtree.contains(&42));
-        assert!(quadtree.contains(&5));
-        assert_eq!(quadtree.size(), 2);
+        assert!(quadtree.validate().is_err());
     }
 
     #[test]
-    fn not_contains_not_inserted_element() {
-        let quadtree = Quadtree::default();
-        assert!(!quadtree.contains(&666));
+    fn validate_detects_missing_element() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 2);
+        let id = quadtree.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+        quadtree.root.elements.remove(&id);
+        quadtree.root.size = 0;
+
+        assert!(quadtree.validate().is_err());
     }
 
-    // Element access
+    // Threading
     #[test]
-    fn get_no_element_in_empty() {
-        let quadtree: Quadtree<i32> = Quadtree::default();
-        assert_eq!(
-            quadtree.get_contained(Rect::new(10.0, 10.0, 10.0, 10.0)),
-            Vec::<&i32>::new()
-        )
+    fn quadtree_is_send_and_sync() {
+        fn assert_send_sync<S: Send + Sync>() {}
+        assert_send_sync::<Quadtree<i32>>();
     }
 
-    // Element access contained
     #[test]
-    fn get_contained_after_one_insertion() {
-        let mut quadtree: Quadtree<i32> = Quadtree::default();
+    #[cfg(feature = "std")]
+    fn concurrent_readers() {
+        let mut quadtree = Quadtree::default();
         quadtree.insert(42, Rect::new(10.0, 10.0, 10.0, 10.0));
+        let quadtree = std::sync::RwLock::new(quadtree);
+
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    let quadtree = quadtree.read().unwrap();
+                    assert_eq!(
+                        quadtree.get_overlapped(Rect::new(15.0, 15.0, 1.0, 1.0)),
+                        vec![&42]
+                    );
+                });
+            }
+        });
+    }
 
-        assert_eq!(
-            quadtree.get_contained(Rect::new(10.0, 10.0, 10.0, 10.0)),
-            vec![&42]
-        )
+    // Rebalancing
+    #[test]
+    fn prune_empty_quadrant() {
+        let mut quadtree = Quadtree::with_fuse_threshold(Rect::new(0.0, 0.0, 100.0, 100.0), 4, 0);
+        quadtree.pre_subdivide(Rect::new(0.0, 0.0, 100.0, 100.0), 2);
+        let removed = quadtree.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+        quadtree.insert(2, Rect::new(80.0, 80.0, 5.0, 5.0));
+        quadtree.remove(removed);
+
+        quadtree.prune_empty_nodes();
+
+        let children = quadtree.root().children().unwrap();
+        assert!(children[0].is_leaf());
+        assert!(children[1].is_leaf());
+        assert!(children[3].is_node());
+        assert_eq!(quadtree.validate(), Ok(()));
     }
 
     #[test]
-    fn get_two_contained_element_after_two_insertions() {
-        let mut quadtree: Quadtree<i32> = Quadtree::default();
-        quadtree.insert(42, Rect::new(10.0, 10.0, 10.0, 10.0));
-        quadtree.insert(5, Rect::new(11.0, 14.0, 5.0, 2.0));
+    fn pre_subdivide_region() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 4);
+        quadtree.insert(1, Rect::new(80.0, 80.0, 5.0, 5.0));
 
-        let elements = quadtree.get_contained(Rect::new(10.0, 10.0, 10.0, 10.0));
+        quadtree.pre_subdivide(Rect::new(10.0, 10.0, 10.0, 10.0), 3);
 
-        assert!(elements.contains(&&42));
-        assert!(elements.contains(&&5));
+        assert_eq!(quadtree.height(), 3);
+        assert_eq!(quadtree.nodes().count(), 13);
+        assert_eq!(quadtree.node_at_point((15.0, 15.0)).unwrap().depth(), 3);
+        assert_eq!(quadtree.node_at_point((80.0, 80.0)).unwrap().depth(), 1);
+
+        for i in 0..8 {
+            quadtree.insert(10 + i, Rect::new(10.0 + i as f32, 10.0, 1.0, 1.0));
+        }
+        assert_eq!(
+            quadtree
+                .get_overlapped(Rect::new(0.0, 0.0, 100.0, 100.0))
+                .len(),
+            9
+        );
+        assert_eq!(quadtree.root.size(), 9);
     }
 
     #[test]
-    fn get_only_one_contained_element_after_two_insertions() {
-        let mut quadtree: Quadtree<i32> = Quadtree::default();
-        quadtree.insert(42, Rect::new(10.0, 10.0, 10.0, 10.0));
-        quadtree.insert(5, Rect::new(15.0, 10.0, 10.0, 10.0));
+    fn rebalance_after_moves_keeps_query_results() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 2);
+        let mut ids = Vec::new();
+        for i in 0..16 {
+            let x = (i % 4) as f32 * 5.0;
+            let y = (i / 4) as f32 * 5.0;
+            ids.push(quadtree.insert(i, Rect::new(x, y, 2.0, 2.0)));
+        }
 
-        assert_eq!(
-            quadtree.get_contained(Rect::new(10.0, 10.0, 10.0, 10.0)),
-            vec![&42]
-        )
+        for (i, id) in ids.iter().enumerate() {
+            let x = 40.0 + (i % 4) as f32 * 12.0;
+            let y = 40.0 + (i / 4) as f32 * 12.0;
+            quadtree
+                .entry_mut(*id)
+                .move_entry(Rect::new(x, y, 4.0, 4.0));
+        }
+
+        let query = Rect::new(30.0, 30.0, 40.0, 40.0);
+        let mut before: Vec<i32> = quadtree
+            .get_overlapped(query)
+            .into_iter()
+            .copied()
+            .collect();
+        let nodes_before = quadtree.nodes().count();
+
+        quadtree.rebalance();
+
+        let mut after: Vec<i32> = quadtree
+            .get_overlapped(query)
+            .into_iter()
+            .copied()
+            .collect();
+        before.sort();
+        after.sort();
+
+        assert_eq!(before, after);
+        assert_eq!(quadtree.size(), 16);
+        assert_eq!(quadtree.root.size(), 16);
+        assert!(quadtree.nodes().count() <= nodes_before);
+        for id in ids {
+            assert!(quadtree.elements.contains_key(&id));
+        }
     }
 
-    // Element access overlapped
     #[test]
-    fn get_overlapped_after_one_insertion() {
-        let mut quadtree: Quadtree<i32> = Quadtree::default();
-        quadtree.insert(42, Rect::new(10.0, 10.0, 10.0, 10.0));
+    fn try_move_entry_out_of_bounds() {
+        let mut quadtree = Quadtree::default();
+        let region = Rect::new(10.0, 10.0, 10.0, 10.0);
+        let entry_id = quadtree.insert(42, region);
+
+        let outside = Rect::new(150.0, 150.0, 10.0, 10.0);
+        let mut entry = quadtree.entry_mut(entry_id);
 
         assert_eq!(
-            quadtree.get_overlapped(Rect::new(15.0, 10.0, 10.0, 10.0)),
-            vec![&42]
-        )
+            entry.try_move_entry(outside),
+            Err(QuadtreeError::OutOfBounds(outside))
+        );
+        assert_eq!(entry.region(), region);
+        assert_eq!(quadtree.get_contained(region), vec![&42]);
     }
 
     #[test]
-    fn get_two_overlapped_element_after_two_insertions() {
-        let mut quadtree: Quadtree<i32> = Quadtree::default();
-        quadtree.insert(42, Rect::new(10.0, 10.0, 10.0, 10.0));
-        quadtree.insert(5, Rect::new(15.0, 14.0, 15.0, 2.0));
+    fn try_move_entry_in_bounds() {
+        let mut quadtree = Quadtree::default();
+        let entry_id = quadtree.insert(42, Rect::new(10.0, 10.0, 10.0, 10.0));
 
-        let elements = quadtree.get_overlapped(Rect::new(10.0, 10.0, 10.0, 10.0));
+        let mut entry = quadtree.entry_mut(entry_id);
 
-        assert!(elements.contains(&&42));
-        assert!(elements.contains(&&5));
+        assert_eq!(
+            entry.try_move_entry(Rect::new(-20.0, -20.0, 5.0, 5.0)),
+            Ok(())
+        );
+        assert_eq!(
+            quadtree.get_contained(Rect::new(-20.0, -20.0, 5.0, 5.0)),
+            vec![&42]
+        );
     }
 
     #[test]
-    fn get_only_one_overlapped_element_after_two_insertions() {
-        let mut quadtree: Quadtree<i32> = Quadtree::default();
-        quadtree.insert(42, Rect::new(10.0, 10.0, 10.0, 10.0));
-        quadtree.insert(5, Rect::new(35.0, 10.0, 10.0, 10.0));
+    fn entry_mut_at_point_picks_smallest_region() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 1);
+        quadtree.insert(1, Rect::new(0.0, 0.0, 60.0, 60.0));
+        let small = quadtree.insert(2, Rect::new(10.0, 10.0, 5.0, 5.0));
+        quadtree.insert(3, Rect::new(12.0, 12.0, 10.0, 10.0));
+        quadtree.insert(4, Rect::new(80.0, 80.0, 5.0, 5.0));
 
+        let mut entry = quadtree.entry_mut_at_point((12.0, 12.0)).unwrap();
+        assert_eq!(entry.id(), small);
+
+        entry.move_entry(Rect::new(50.0, 80.0, 5.0, 5.0));
         assert_eq!(
-            quadtree.get_overlapped(Rect::new(10.0, 10.0, 10.0, 10.0)),
-            vec![&42]
-        )
+            quadtree.get_contained(Rect::new(50.0, 80.0, 5.0, 5.0)),
+            vec![&2]
+        );
     }
 
-    // Removing
     #[test]
-    fn remove_one_element() {
-        let mut quadtree: Quadtree<i32> = Quadtree::default();
-        let value = 42;
-        let region = Rect::new(10.0, 10.0, 10.0, 10.0);
-        let id = quadtree.insert(value, region);
+    fn entry_mut_at_point_ties_by_id() {
+        let mut quadtree = Quadtree::default();
+        let first = quadtree.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+        quadtree.insert(2, Rect::new(12.0, 12.0, 5.0, 5.0));
 
-        assert_eq!(quadtree.remove(id).unwrap(), (value, region));
+        assert_eq!(
+            quadtree.entry_mut_at_point((13.0, 13.0)).unwrap().id(),
+            first
+        );
+        assert!(quadtree.entry_mut_at_point((50.0, 50.0)).is_none());
     }
 
-    // Entries
+    // Iteration
     #[test]
-    fn entry() {
+    fn iter_yields_ids_values_and_regions() {
         let mut quadtree = Quadtree::default();
-        let entry_id = quadtree.insert(42, Rect::new(10.0, 10.0, 10.0, 10.0));
+        let a = quadtree.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+        let b = quadtree.insert(2, Rect::new(-10.0, 10.0, 5.0, 5.0));
 
-        let entry = quadtree.entry(entry_id);
+        let mut items: Vec<(u64, &i32, Rect)> = quadtree.iter().collect();
+        items.sort_by_key(|(id, _, _)| *id);
 
-        assert_eq!(entry.value(), &42);
-        assert_eq!(entry.id(), entry_id);
+        assert_eq!(
+            items,
+            vec![
+                (a, &1, Rect::new(10.0, 10.0, 5.0, 5.0)),
+                (b, &2, Rect::new(-10.0, 10.0, 5.0, 5.0))
+            ]
+        );
     }
 
     #[test]
-    fn move_entry() {
+    fn iter_mut_modifies_values() {
         let mut quadtree = Quadtree::default();
-        let entry_id = quadtree.insert(42, Rect::new(10.0, 10.0, 10.0, 10.0));
+        let a = quadtree.insert(1, Rect::new(10.0, 10.0, 5.0, 5.0));
+        let b = quadtree.insert(2, Rect::new(-10.0, 10.0, 5.0, 5.0));
 
-        let mut entry = quadtree.entry_mut(entry_id);
-        entry.move_entry(Rect::new(20.0, 20.0, 5.0, 5.0));
+        for (_, value, _) in quadtree.iter_mut() {
+            *value *= 10;
+        }
 
-        assert_eq!(
-            quadtree.get_contained(Rect::new(20.0, 20.0, 5.0, 5.0)),
-            vec![&42]
-        );
+        assert_eq!(quadtree.entry(a).value(), &10);
+        assert_eq!(quadtree.entry(b).value(), &20);
     }
 
-    // Iteration
     #[test]
     fn move_elements_in_iteration() {
         let mut quadtree = Quadtree::default();
@@ -631,11 +4571,11 @@ mod tests {
         let e6 = quadtree.insert(6, Rect::new(10.0, 20.0, 10.0, 10.0));
         let e7 = quadtree.insert(7, Rect::new(0.0, 10.0, 10.0, 10.0));
 
-        for mut entry in quadtree.entries_mut() {
+        quadtree.for_each_entry_mut(|mut entry| {
             let mut region = entry.region();
             region.y -= 2.0;
             entry.move_entry(region);
-        }
+        });
 
         assert!(quadtree.root.is_node());
 
@@ -655,6 +4595,28 @@ mod tests {
             .elements
             .contains_key(&e7));
     }
+
+    #[test]
+    fn remove_elements_in_iteration() {
+        let mut quadtree = Quadtree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 2);
+        for i in 0..20 {
+            let offset = (i * 5) as f32;
+            quadtree.insert(i, Rect::new(offset, offset, 2.0, 2.0));
+        }
+
+        let mut visited = 0;
+        quadtree.for_each_entry_mut(|entry| {
+            visited += 1;
+            if entry.value() % 2 == 0 {
+                entry.remove();
+            }
+        });
+
+        assert_eq!(visited, 20);
+        assert_eq!(quadtree.size(), 10);
+        assert!(quadtree.iter().all(|(_, value, _)| value % 2 == 1));
+        assert!(quadtree.validate().is_ok());
+    }
 }
 
 #[cfg(test)]
@@ -676,7 +4638,7 @@ mod node_tests {
         let mut node = Node::new(Rect::new(0.0, 0.0, 50.0, 50.0));
         let id = 0;
         let region = Rect::new(10.0, 10.0, 10.0, 10.0);
-        node.insert(id, region, 5);
+        node.insert(id, region, 5, u32::MAX);
 
         assert!(node.is_leaf());
         assert!(!node.elements.is_empty());
@@ -688,20 +4650,40 @@ mod node_tests {
     #[should_panic]
     fn add_one_element_outside_node_region() {
         let mut node = Node::new(Rect::new(0.0, 0.0, 50.0, 50.0));
-        node.insert(0, Rect::new(-10.0, -10.0, 10.0, 10.0), 5);
+        node.insert(0, Rect::new(-10.0, -10.0, 10.0, 10.0), 5, u32::MAX);
     }
 
     #[test]
     fn add_elements_until_subdivision() {
         let mut node = Node::new(Rect::new(0.0, 0.0, 50.0, 50.0));
         let max_node_capacity = 3;
-        node.insert(0, Rect::new(10.0, 10.0, 10.0, 10.0), max_node_capacity);
-        node.insert(1, Rect::new(20.0, 20.0, 10.0, 10.0), max_node_capacity);
-        node.insert(2, Rect::new(30.0, 10.0, 10.0, 20.0), max_node_capacity);
+        node.insert(
+            0,
+            Rect::new(10.0, 10.0, 10.0, 10.0),
+            max_node_capacity,
+            u32::MAX,
+        );
+        node.insert(
+            1,
+            Rect::new(20.0, 20.0, 10.0, 10.0),
+            max_node_capacity,
+            u32::MAX,
+        );
+        node.insert(
+            2,
+            Rect::new(30.0, 10.0, 10.0, 20.0),
+            max_node_capacity,
+            u32::MAX,
+        );
 
         assert!(node.is_leaf());
 
-        node.insert(3, Rect::new(10.0, 15.0, 20.0, 20.0), max_node_capacity);
+        node.insert(
+            3,
+            Rect::new(10.0, 15.0, 20.0, 20.0),
+            max_node_capacity,
+            u32::MAX,
+        );
 
         assert!(!node.is_leaf());
         assert!(node.elements.contains_key(&1));
@@ -720,7 +4702,7 @@ mod node_tests {
         let mut node = Node::new(Rect::new(0.0, 0.0, 50.0, 50.0));
         let id = 0;
         let region = Rect::new(10.0, 10.0, 10.0, 10.0);
-        node.insert(id, region, 5);
+        node.insert(id, region, 5, u32::MAX);
 
         node.remove(id, region, 5);
 
@@ -732,10 +4714,30 @@ mod node_tests {
     fn after_subdivision_remove_child_element_to_fuse() {
         let mut node = Node::new(Rect::new(0.0, 0.0, 50.0, 50.0));
         let max_node_capacity = 3;
-        node.insert(0, Rect::new(10.0, 10.0, 10.0, 10.0), max_node_capacity);
-        node.insert(1, Rect::new(20.0, 20.0, 10.0, 10.0), max_node_capacity);
-        node.insert(2, Rect::new(30.0, 10.0, 10.0, 20.0), max_node_capacity);
-        node.insert(3, Rect::new(10.0, 15.0, 20.0, 20.0), max_node_capacity);
+        node.insert(
+            0,
+            Rect::new(10.0, 10.0, 10.0, 10.0),
+            max_node_capacity,
+            u32::MAX,
+        );
+        node.insert(
+            1,
+            Rect::new(20.0, 20.0, 10.0, 10.0),
+            max_node_capacity,
+            u32::MAX,
+        );
+        node.insert(
+            2,
+            Rect::new(30.0, 10.0, 10.0, 20.0),
+            max_node_capacity,
+            u32::MAX,
+        );
+        node.insert(
+            3,
+            Rect::new(10.0, 15.0, 20.0, 20.0),
+            max_node_capacity,
+            u32::MAX,
+        );
 
         node.remove(0, Rect::new(10.0, 10.0, 10.0, 10.0), max_node_capacity);
 
@@ -743,21 +4745,123 @@ mod node_tests {
         assert!(node.is_leaf());
     }
 
+    #[test]
+    fn removing_never_leaves_over_capacity_leaf() {
+        let mut node = Node::new(Rect::new(0.0, 0.0, 100.0, 100.0));
+        let max_node_capacity = 3;
+
+        let mut regions = Vec::new();
+        for (i, (x, y)) in [(5.0, 5.0), (55.0, 5.0), (5.0, 55.0), (55.0, 55.0)]
+            .into_iter()
+            .enumerate()
+        {
+            for j in 0..3 {
+                let id = (i * 3 + j) as u64;
+                let region = Rect::new(x + j as f32 * 10.0, y, 5.0, 5.0);
+                node.insert(id, region, max_node_capacity, u32::MAX);
+                regions.push((id, region));
+            }
+        }
+
+        assert!(node.is_node());
+
+        for (id, region) in regions {
+            node.remove(id, region, max_node_capacity);
+
+            let nodes = NodeIter {
+                nodes_to_process: vec![&node],
+            };
+            for n in nodes.filter(|n| n.is_leaf()) {
+                assert!(n.elements.len() <= max_node_capacity);
+                assert_eq!(n.elements.len(), n.size);
+            }
+        }
+
+        assert!(node.is_leaf());
+        assert_eq!(node.size, 0);
+    }
+
     // Moving elements
+    #[test]
+    fn moving_element_inside_leaf_updates_in_place() {
+        let mut node = Node::new(Rect::new(0.0, 0.0, 50.0, 50.0));
+        let max_node_capacity = 3;
+        node.insert(
+            0,
+            Rect::new(10.0, 10.0, 10.0, 10.0),
+            max_node_capacity,
+            u32::MAX,
+        );
+        node.insert(
+            1,
+            Rect::new(20.0, 20.0, 10.0, 10.0),
+            max_node_capacity,
+            u32::MAX,
+        );
+        node.insert(
+            2,
+            Rect::new(30.0, 10.0, 10.0, 20.0),
+            max_node_capacity,
+            u32::MAX,
+        );
+        node.insert(
+            3,
+            Rect::new(10.0, 15.0, 20.0, 20.0),
+            max_node_capacity,
+            u32::MAX,
+        );
+
+        node.move_element(
+            0,
+            Rect::new(10.0, 10.0, 10.0, 10.0),
+            Rect::new(11.0, 12.0, 10.0, 10.0),
+            max_node_capacity,
+            u32::MAX,
+            max_node_capacity,
+        );
+
+        let children = node.children.unwrap();
+        assert_eq!(children[0].elements[&0], Rect::new(11.0, 12.0, 10.0, 10.0));
+        assert_eq!(children[0].size, 1);
+        assert_eq!(node.size, 4);
+    }
+
     #[test]
     fn moving_element_to_parent_node() {
         let mut node = Node::new(Rect::new(0.0, 0.0, 50.0, 50.0));
         let max_node_capacity = 3;
-        node.insert(0, Rect::new(10.0, 10.0, 10.0, 10.0), max_node_capacity);
-        node.insert(1, Rect::new(20.0, 20.0, 10.0, 10.0), max_node_capacity);
-        node.insert(2, Rect::new(30.0, 10.0, 10.0, 20.0), max_node_capacity);
-        node.insert(3, Rect::new(10.0, 15.0, 20.0, 20.0), max_node_capacity);
+        node.insert(
+            0,
+            Rect::new(10.0, 10.0, 10.0, 10.0),
+            max_node_capacity,
+            u32::MAX,
+        );
+        node.insert(
+            1,
+            Rect::new(20.0, 20.0, 10.0, 10.0),
+            max_node_capacity,
+            u32::MAX,
+        );
+        node.insert(
+            2,
+            Rect::new(30.0, 10.0, 10.0, 20.0),
+            max_node_capacity,
+            u32::MAX,
+        );
+        node.insert(
+            3,
+            Rect::new(10.0, 15.0, 20.0, 20.0),
+            max_node_capacity,
+            u32::MAX,
+        );
 
         node.move_element(
             0,
             Rect::new(10.0, 10.0, 10.0, 10.0),
             Rect::new(10.0, 20.0, 10.0, 10.0),
             max_node_capacity,
+            u32::MAX,
+            max_node_capacity,
         );
 
         assert!(node.elements.contains_key(&0));
@@ -770,16 +4874,38 @@ mod node_tests {
     fn moving_element_to_other_child() {
         let mut node = Node::new(Rect::new(0.0, 0.0, 50.0, 50.0));
         let max_node_capacity = 3;
-        node.insert(0, Rect::new(10.0, 10.0, 10.0, 10.0), max_node_capacity);
-        node.insert(1, Rect::new(20.0, 20.0, 10.0, 10.0), max_node_capacity);
-        node.insert(2, Rect::new(30.0, 10.0, 10.0, 20.0), max_node_capacity);
-        node.insert(3, Rect::new(10.0, 15.0, 20.0, 20.0), max_node_capacity);
+        node.insert(
+            0,
+            Rect::new(10.0, 10.0, 10.0, 10.0),
+            max_node_capacity,
+            u32::MAX,
+        );
+        node.insert(
+            1,
+            Rect::new(20.0, 20.0, 10.0, 10.0),
+            max_node_capacity,
+            u32::MAX,
+        );
+        node.insert(
+            2,
+            Rect::new(30.0, 10.0, 10.0, 20.0),
+            max_node_capacity,
+            u32::MAX,
+        );
+        node.insert(
+            3,
+            Rect::new(10.0, 15.0, 20.0, 20.0),
+            max_node_capacity,
+            u32::MAX,
+        );
 
         node.move_element(
             0,
             Rect::new(10.0, 10.0, 10.0, 10.0),
             Rect::new(10.0, 30.0, 10.0, 10.0),
             max_node_capacity,
+            u32::MAX,
+            max_node_capacity,
         );
 
         assert!(!node.elements.contains_key(&0));
@@ -792,16 +4918,38 @@ mod node_tests {
     fn moving_element_to_child() {
         let mut node = Node::new(Rect::new(0.0, 0.0, 50.0, 50.0));
         let max_node_capacity = 3;
-        node.insert(0, Rect::new(10.0, 10.0, 10.0, 10.0), max_node_capacity);
-        node.insert(1, Rect::new(20.0, 20.0, 10.0, 10.0), max_node_capacity);
-        node.insert(2, Rect::new(30.0, 10.0, 10.0, 20.0), max_node_capacity);
-        node.insert(3, Rect::new(10.0, 15.0, 20.0, 20.0), max_node_capacity);
+        node.insert(
+            0,
+            Rect::new(10.0, 10.0, 10.0, 10.0),
+            max_node_capacity,
+            u32::MAX,
+        );
+        node.insert(
+            1,
+            Rect::new(20.0, 20.0, 10.0, 10.0),
+            max_node_capacity,
+            u32::MAX,
+        );
+        node.insert(
+            2,
+            Rect::new(30.0, 10.0, 10.0, 20.0),
+            max_node_capacity,
+            u32::MAX,
+        );
+        node.insert(
+            3,
+            Rect::new(10.0, 15.0, 20.0, 20.0),
+            max_node_capacity,
+            u32::MAX,
+        );
 
         node.move_element(
             1,
             Rect::new(20.0, 20.0, 10.0, 10.0),
             Rect::new(10.0, 30.0, 10.0, 10.0),
             max_node_capacity,
+            u32::MAX,
+            max_node_capacity,
         );
 
         assert!(!node.elements.contains_key(&1));
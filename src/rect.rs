@@ -1,3 +1,8 @@
+use alloc::{vec, vec::Vec};
+use core::hash::{Hash, Hasher};
+
+use crate::util::{ceil, floor, sqrt};
+
 #[derive(Debug, Default, Clone, Copy, PartialEq)]
 pub struct Rect {
     pub x: f32,
@@ -6,7 +11,21 @@ pub struct Rect {
     pub h: f32,
 }
 
+/// Hashable key wrapping a [`Rect`], compared by the bit patterns of its fields.
+///
+/// Unlike `Rect`'s float comparison, `0.0` and `-0.0` are different keys and a
+/// NaN field is equal to the same NaN bit pattern.
+#[derive(Debug, Clone, Copy)]
+pub struct RectKey(pub Rect);
+
 impl Rect {
+    pub const ZERO: Self = Self {
+        x: 0.0,
+        y: 0.0,
+        w: 0.0,
+        h: 0.0,
+    };
+
     pub fn new(x: f32, y: f32, w: f32, h: f32) -> Self {
         Self { x, y, w, h }
     }
@@ -20,6 +39,129 @@ impl Rect {
         }
     }
 
+    pub fn from_center_size(center: (f32, f32), size: (f32, f32)) -> Self {
+        Self::new_centered(center.0, center.1, size.0, size.1)
+    }
+
+    /// Rect spanning two opposite corners given in any order, so width and
+    /// height are never negative.
+    pub fn from_corners(a: (f32, f32), b: (f32, f32)) -> Self {
+        let x = a.0.min(b.0);
+        let y = a.1.min(b.1);
+
+        Self {
+            x,
+            y,
+            w: a.0.max(b.0) - x,
+            h: a.1.max(b.1) - y,
+        }
+    }
+
+    /// Smallest rect containing every point, `None` for an empty slice.
+    /// A single point gives a zero-size rect at that point.
+    pub fn from_points(points: &[(f32, f32)]) -> Option<Self> {
+        let (first, rest) = points.split_first()?;
+
+        let (mut min, mut max) = (*first, *first);
+        for &(x, y) in rest {
+            min = (min.0.min(x), min.1.min(y));
+            max = (max.0.max(x), max.1.max(y));
+        }
+
+        Some(Self::from_corners(min, max))
+    }
+
+    /// The same area with a non-negative size, e.g. for a rect dragged up and
+    /// to the left. The quadtree rejects rects with a negative size, so
+    /// normalize them before inserting.
+    pub fn normalize(&self) -> Rect {
+        Self::from_corners((self.x, self.y), (self.x + self.w, self.y + self.h))
+    }
+
+    /// `true` when all coordinates are finite and the size is not negative.
+    pub fn is_valid(&self) -> bool {
+        self.x.is_finite()
+            && self.y.is_finite()
+            && self.w.is_finite()
+            && self.h.is_finite()
+            && self.w >= 0.0
+            && self.h >= 0.0
+    }
+
+    /// Top left corner.
+    pub fn min(&self) -> (f32, f32) {
+        (self.x, self.y)
+    }
+
+    /// Bottom right corner.
+    pub fn max(&self) -> (f32, f32) {
+        (self.x + self.w, self.y + self.h)
+    }
+
+    pub fn center(&self) -> (f32, f32) {
+        (self.x + self.w / 2.0, self.y + self.h / 2.0)
+    }
+
+    /// Center and half the size, the inverse of [`Rect::new_centered`].
+    pub fn center_half_extents(&self) -> ((f32, f32), (f32, f32)) {
+        (self.center(), (self.w / 2.0, self.h / 2.0))
+    }
+
+    pub fn area(&self) -> f32 {
+        self.w * self.h
+    }
+
+    /// Width over height, infinite for a zero height and NaN for an empty rect.
+    pub fn aspect_ratio(&self) -> f32 {
+        self.w / self.h
+    }
+
+    /// The four equal sub-rects, in the order nodes store their children:
+    /// top left, top right, bottom left, bottom right.
+    pub fn quadrants(&self) -> [Rect; 4] {
+        let w = self.w / 2.0;
+        let h = self.h / 2.0;
+
+        [
+            Rect::new(self.x, self.y, w, h),
+            Rect::new(self.x + w, self.y, w, h),
+            Rect::new(self.x, self.y + h, w, h),
+            Rect::new(self.x + w, self.y + h, w, h),
+        ]
+    }
+
+    /// Top and bottom parts split at `at_y`, which is clamped to the rect, so
+    /// a split outside of it gives a zero-height part on that side.
+    pub fn split_horizontal(&self, at_y: f32) -> (Rect, Rect) {
+        let h = (at_y - self.y).max(0.0).min(self.h);
+
+        (
+            Rect::new(self.x, self.y, self.w, h),
+            Rect::new(self.x, self.y + h, self.w, self.h - h),
+        )
+    }
+
+    /// Left and right parts split at `at_x`, which is clamped to the rect, so
+    /// a split outside of it gives a zero-width part on that side.
+    pub fn split_vertical(&self, at_x: f32) -> (Rect, Rect) {
+        let w = (at_x - self.x).max(0.0).min(self.w);
+
+        (
+            Rect::new(self.x, self.y, w, self.h),
+            Rect::new(self.x + w, self.y, self.w - w, self.h),
+        )
+    }
+
+    /// Index in [`Rect::quadrants`] of the quadrant containing `child`, or
+    /// `None` if it straddles a split line. On a shared edge the first
+    /// quadrant wins, like in the quadtree.
+    pub fn quadrant_index(&self, child: &Rect) -> Option<usize> {
+        self.quadrants()
+            .iter()
+            .position(|quadrant| quadrant.contains(child))
+    }
+
+    /// Closed containment: `other` may touch any edge of `self`.
     pub fn contains(&self, other: &Self) -> bool {
         self.x <= other.x
             && self.y <= other.y
@@ -27,10 +169,921 @@ impl Rect {
             && self.y + self.h >= other.y + other.h
     }
 
-    pub fn overlapps(&self, other: &Self) -> bool {
+    /// Closed containment in `self` shrunk by `margin` on every side, a
+    /// negative margin grows it instead. Always `false` once the margins
+    /// cross, even for a zero-size `other`.
+    pub fn contains_with_margin(&self, other: &Self, margin: f32) -> bool {
+        self.x + margin <= other.x
+            && self.y + margin <= other.y
+            && self.x + self.w - margin >= other.x + other.w
+            && self.y + self.h - margin >= other.y + other.h
+    }
+
+    /// Half-open containment over `[x, x + w) x [y, y + h)`: `other` may touch
+    /// the min edges of `self` but must end strictly before its max edges, so a
+    /// rect touching a shared edge between two tiles belongs to one of them only.
+    ///
+    /// The quadtree keeps using [`Rect::contains`] to pick children, which is
+    /// already deterministic (first child in order) and lets elements touching
+    /// the root's max edges descend.
+    pub fn contains_strict(&self, other: &Self) -> bool {
+        self.x <= other.x
+            && self.y <= other.y
+            && self.x + self.w > other.x + other.w
+            && self.y + self.h > other.y + other.h
+    }
+
+    /// Same as [`Rect::contains_point_inclusive`]. The quadtree's point
+    /// queries use this, so a point on a shared edge matches both sides.
+    pub fn contains_point(&self, x: f32, y: f32) -> bool {
+        self.contains_point_inclusive(x, y)
+    }
+
+    /// Closed test: points on any edge are inside.
+    pub fn contains_point_inclusive(&self, x: f32, y: f32) -> bool {
+        self.x <= x && self.y <= y && self.x + self.w >= x && self.y + self.h >= y
+    }
+
+    /// Half-open test over `[x, x + w) x [y, y + h)`, so a point on an edge
+    /// shared by tiles belongs to exactly one of them.
+    pub fn contains_point_exclusive(&self, x: f32, y: f32) -> bool {
+        self.x <= x && self.y <= y && self.x + self.w > x && self.y + self.h > y
+    }
+
+    /// Closed overlap: rects touching on an edge or corner overlap.
+    pub fn overlaps(&self, other: &Self) -> bool {
         self.x <= other.x + other.w
             && self.x + self.w >= other.x
             && self.y <= other.y + other.h
             && self.y + self.h >= other.y
     }
+
+    #[deprecated(note = "renamed to `overlaps`")]
+    pub fn overlapps(&self, other: &Self) -> bool {
+        self.overlaps(other)
+    }
+
+    pub fn intersection(&self, other: &Self) -> Option<Self> {
+        if !self.overlaps(other) {
+            return None;
+        }
+
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let w = (self.x + self.w).min(other.x + other.w) - x;
+        let h = (self.y + self.h).min(other.y + other.h) - y;
+
+        Some(Self { x, y, w, h })
+    }
+
+    /// Parts of `self` not covered by `other`: up to a full-width strip above
+    /// and below the covered part, and a strip left and right of it. Empty
+    /// pieces are left out, so full cover gives no rect and no overlap gives
+    /// `self` back.
+    pub fn subtract(&self, other: &Rect) -> Vec<Rect> {
+        let Some(covered) = self.intersection(other) else {
+            return vec![*self];
+        };
+        if covered.area() <= 0.0 {
+            return vec![*self];
+        }
+
+        let right = self.x + self.w;
+        let bottom = self.y + self.h;
+        let covered_right = covered.x + covered.w;
+        let covered_bottom = covered.y + covered.h;
+
+        [
+            Rect::new(self.x, self.y, self.w, covered.y - self.y),
+            Rect::new(self.x, covered_bottom, self.w, bottom - covered_bottom),
+            Rect::new(self.x, covered.y, covered.x - self.x, covered.h),
+            Rect::new(covered_right, covered.y, right - covered_right, covered.h),
+        ]
+        .into_iter()
+        .filter(|piece| piece.area() > 0.0)
+        .collect()
+    }
+
+    /// Part of `self` lying within `bounds`. Unlike [`Rect::intersection`] this
+    /// always returns a rect: when the two are disjoint, the result has zero
+    /// width and/or height and sits on the edge of `bounds` closest to `self`.
+    pub fn clamp(&self, bounds: &Self) -> Self {
+        let (min_x, max_x) = (bounds.x, bounds.x + bounds.w);
+        let (min_y, max_y) = (bounds.y, bounds.y + bounds.h);
+
+        let x = self.x.clamp(min_x, max_x);
+        let y = self.y.clamp(min_y, max_y);
+
+        Self {
+            x,
+            y,
+            w: (self.x + self.w).clamp(min_x, max_x) - x,
+            h: (self.y + self.h).clamp(min_y, max_y) - y,
+        }
+    }
+
+    /// Expands the rect outward to the grid of `cell_size` cells anchored at the
+    /// origin: the min corner is floored and the max corner ceiled, which also
+    /// rounds negative coordinates away from the rect. The grid always starts at
+    /// `0`, whether or not `cell_size` evenly divides the world.
+    pub fn snap_to_grid(&self, cell_size: f32) -> Self {
+        let x = floor(self.x / cell_size) * cell_size;
+        let y = floor(self.y / cell_size) * cell_size;
+        let max_x = ceil((self.x + self.w) / cell_size) * cell_size;
+        let max_y = ceil((self.y + self.h) / cell_size) * cell_size;
+
+        Self {
+            x,
+            y,
+            w: max_x - x,
+            h: max_y - y,
+        }
+    }
+
+    /// Smallest rect containing both `self` and `other`.
+    pub fn union(&self, other: &Self) -> Self {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        let w = (self.x + self.w).max(other.x + other.w) - x;
+        let h = (self.y + self.h).max(other.y + other.h) - y;
+
+        Self { x, y, w, h }
+    }
+
+    /// Smallest rect containing every rect of `rects`, `None` when empty.
+    pub fn hull_of(rects: &[Rect]) -> Option<Rect> {
+        rects.iter().copied().reduce(|a, b| a.union(&b))
+    }
+
+    /// Area shared by both rects, `0` when they are disjoint or only touch.
+    pub fn overlap_area(&self, other: &Self) -> f32 {
+        let w = (self.x + self.w).min(other.x + other.w) - self.x.max(other.x);
+        let h = (self.y + self.h).min(other.y + other.h) - self.y.max(other.y);
+
+        w.max(0.0) * h.max(0.0)
+    }
+
+    /// Intersection area over union area, `0` for disjoint rects and `1` for
+    /// identical ones. Two empty rects have an IoU of `0`.
+    pub fn iou(&self, other: &Self) -> f32 {
+        let intersection = self.overlap_area(other);
+        let union = self.area() + other.area() - intersection;
+
+        if union <= 0.0 {
+            return 0.0;
+        }
+
+        intersection / union
+    }
+
+    pub fn translate(&self, dx: f32, dy: f32) -> Rect {
+        Rect::new(self.x + dx, self.y + dy, self.w, self.h)
+    }
+
+    /// Largest copy of `self` with the same aspect ratio that fits in `bounds`,
+    /// centered in it. A zero-size rect stays zero-size at the center.
+    pub fn fit_inside(&self, bounds: &Rect) -> Rect {
+        let scale = (bounds.w / self.w).min(bounds.h / self.h);
+        let (cx, cy) = bounds.center();
+
+        if !scale.is_finite() {
+            return Rect::new_centered(cx, cy, 0.0, 0.0);
+        }
+
+        Rect::new_centered(cx, cy, self.w * scale, self.h * scale)
+    }
+
+    /// Scales coordinates and size per axis, anchored at the origin rather
+    /// than at the rect's center. Negative factors give a negative size.
+    pub fn scale_xy(&self, sx: f32, sy: f32) -> Rect {
+        Rect::new(self.x * sx, self.y * sy, self.w * sx, self.h * sy)
+    }
+
+    /// Moves each side inwards by its margin, negative margins grow the rect.
+    /// When opposite sides would cross, that dimension collapses to zero at
+    /// the midpoint between them.
+    pub fn inset(&self, left: f32, top: f32, right: f32, bottom: f32) -> Rect {
+        let collapse = |min: f32, max: f32| {
+            if min <= max {
+                (min, max - min)
+            } else {
+                ((min + max) / 2.0, 0.0)
+            }
+        };
+
+        let (x, w) = collapse(self.x + left, self.x + self.w - right);
+        let (y, h) = collapse(self.y + top, self.y + self.h - bottom);
+
+        Rect::new(x, y, w, h)
+    }
+
+    /// Linear interpolation towards `other`, `t` is not clamped so values
+    /// outside `[0, 1]` extrapolate.
+    pub fn lerp(&self, other: &Self, t: f32) -> Self {
+        Self {
+            x: self.x + (other.x - self.x) * t,
+            y: self.y + (other.y - self.y) * t,
+            w: self.w + (other.w - self.w) * t,
+            h: self.h + (other.h - self.h) * t,
+        }
+    }
+
+    /// Separating axis test against a convex polygon given by its vertices in
+    /// order (either winding). Touching counts as intersecting, like
+    /// [`Rect::overlaps`]. Polygons with fewer than 3 vertices never intersect.
+    pub fn intersects_polygon(&self, vertices: &[(f32, f32)]) -> bool {
+        if vertices.len() < 3 {
+            return false;
+        }
+
+        let corners = [
+            (self.x, self.y),
+            (self.x + self.w, self.y),
+            (self.x + self.w, self.y + self.h),
+            (self.x, self.y + self.h),
+        ];
+
+        let edge_normals = vertices.iter().enumerate().map(|(i, a)| {
+            let b = vertices[(i + 1) % vertices.len()];
+            (a.1 - b.1, b.0 - a.0)
+        });
+
+        [(1.0, 0.0), (0.0, 1.0)]
+            .into_iter()
+            .chain(edge_normals)
+            .all(|axis| {
+                let (rect_min, rect_max) = project(&corners, axis);
+                let (polygon_min, polygon_max) = project(vertices, axis);
+
+                rect_min <= polygon_max && polygon_min <= rect_max
+            })
+    }
+
+    /// Parametric distance `t >= 0` along `origin + t * dir` at which the ray
+    /// first touches the rect, `0` if the origin is inside.
+    pub fn ray_intersection(&self, origin: (f32, f32), dir: (f32, f32)) -> Option<f32> {
+        let mut t_min = 0.0f32;
+        let mut t_max = f32::INFINITY;
+
+        for (o, d, min, max) in [
+            (origin.0, dir.0, self.x, self.x + self.w),
+            (origin.1, dir.1, self.y, self.y + self.h),
+        ] {
+            if d == 0.0 {
+                if o < min || o > max {
+                    return None;
+                }
+                continue;
+            }
+
+            let t1 = (min - o) / d;
+            let t2 = (max - o) / d;
+            t_min = t_min.max(t1.min(t2));
+            t_max = t_max.min(t1.max(t2));
+
+            if t_min > t_max {
+                return None;
+            }
+        }
+
+        Some(t_min)
+    }
+
+    /// The point of the rect closest to `(px, py)`, which is the point itself
+    /// when it is inside.
+    pub fn closest_point(&self, px: f32, py: f32) -> (f32, f32) {
+        let (min, max) = (self.min(), self.max());
+        (px.max(min.0).min(max.0), py.max(min.1).min(max.1))
+    }
+
+    /// Euclidean distance from the point to the rect, `0` if it is inside.
+    pub fn distance_to_point(&self, px: f32, py: f32) -> f32 {
+        let (cx, cy) = self.closest_point(px, py);
+        let dx = px - cx;
+        let dy = py - cy;
+
+        sqrt(dx * dx + dy * dy)
+    }
+
+    pub fn distance_to_rect(&self, other: &Self) -> f32 {
+        let dx = (other.x - (self.x + self.w))
+            .max(self.x - (other.x + other.w))
+            .max(0.0);
+        let dy = (other.y - (self.y + self.h))
+            .max(self.y - (other.y + other.h))
+            .max(0.0);
+
+        sqrt(dx * dx + dy * dy)
+    }
+}
+
+fn project(points: &[(f32, f32)], axis: (f32, f32)) -> (f32, f32) {
+    points
+        .iter()
+        .map(|(x, y)| x * axis.0 + y * axis.1)
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), p| {
+            (min.min(p), max.max(p))
+        })
+}
+
+impl RectKey {
+    fn bits(&self) -> [u32; 4] {
+        [
+            self.0.x.to_bits(),
+            self.0.y.to_bits(),
+            self.0.w.to_bits(),
+            self.0.h.to_bits(),
+        ]
+    }
+}
+
+impl PartialEq for RectKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.bits() == other.bits()
+    }
+}
+
+impl Eq for RectKey {}
+
+impl Hash for RectKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.bits().hash(state);
+    }
+}
+
+impl From<Rect> for RectKey {
+    fn from(rect: Rect) -> Self {
+        Self(rect)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Construction
+    #[test]
+    fn zero_rect() {
+        assert_eq!(Rect::ZERO, Rect::new(0.0, 0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn from_center_size() {
+        assert_eq!(
+            Rect::from_center_size((10.0, 10.0), (4.0, 6.0)),
+            Rect::new(8.0, 7.0, 4.0, 6.0)
+        );
+    }
+
+    #[test]
+    fn corners() {
+        let rect = Rect::new(-2.0, 3.0, 4.0, 5.0);
+
+        assert_eq!(rect.min(), (-2.0, 3.0));
+        assert_eq!(rect.max(), (2.0, 8.0));
+        assert_eq!(Rect::from_corners(rect.min(), rect.max()), rect);
+    }
+
+    #[test]
+    fn center_half_extents_round_trip() {
+        let rect = Rect::new_centered(10.0, -4.0, 6.0, 2.0);
+
+        assert_eq!(rect.center_half_extents(), ((10.0, -4.0), (3.0, 1.0)));
+    }
+
+    #[test]
+    fn from_corners_in_any_order() {
+        let expected = Rect::new(0.0, 5.0, 10.0, 15.0);
+
+        assert_eq!(Rect::from_corners((0.0, 5.0), (10.0, 20.0)), expected);
+        assert_eq!(Rect::from_corners((10.0, 20.0), (0.0, 5.0)), expected);
+        assert_eq!(Rect::from_corners((10.0, 5.0), (0.0, 20.0)), expected);
+    }
+
+    #[test]
+    fn from_points() {
+        assert_eq!(Rect::from_points(&[]), None);
+        assert_eq!(
+            Rect::from_points(&[(3.0, 4.0)]),
+            Some(Rect::new(3.0, 4.0, 0.0, 0.0))
+        );
+        assert_eq!(
+            Rect::from_points(&[(3.0, 4.0), (-1.0, 10.0), (5.0, 0.0)]),
+            Some(Rect::new(-1.0, 0.0, 6.0, 10.0))
+        );
+    }
+
+    #[test]
+    fn aspect_ratio() {
+        assert_eq!(Rect::new(0.0, 0.0, 20.0, 10.0).aspect_ratio(), 2.0);
+        assert_eq!(Rect::new(0.0, 0.0, 10.0, 10.0).aspect_ratio(), 1.0);
+    }
+
+    // Validity
+    #[test]
+    fn valid_rects() {
+        assert!(Rect::new(-10.0, 5.0, 0.0, 3.0).is_valid());
+        assert!(!Rect::new(f32::NAN, 0.0, 1.0, 1.0).is_valid());
+        assert!(!Rect::new(0.0, f32::INFINITY, 1.0, 1.0).is_valid());
+        assert!(!Rect::new(0.0, 0.0, -1.0, 1.0).is_valid());
+        assert!(!Rect::new(0.0, 0.0, 1.0, -1.0).is_valid());
+    }
+
+    #[test]
+    fn normalize_negative_size() {
+        let expected = Rect::new(5.0, 10.0, 5.0, 10.0);
+
+        assert_eq!(Rect::new(10.0, 20.0, -5.0, -10.0).normalize(), expected);
+        assert_eq!(Rect::new(5.0, 20.0, 5.0, -10.0).normalize(), expected);
+        assert_eq!(expected.normalize(), expected);
+        assert!(Rect::new(10.0, 20.0, -5.0, -10.0).normalize().is_valid());
+    }
+
+    // Containment
+    #[test]
+    fn contains_strict_excludes_max_edges() {
+        let left = Rect::new(0.0, 0.0, 50.0, 50.0);
+        let right = Rect::new(50.0, 0.0, 50.0, 50.0);
+        let on_edge = Rect::new(40.0, 10.0, 10.0, 10.0);
+
+        assert!(left.contains(&on_edge));
+        assert!(!left.contains_strict(&on_edge));
+        assert!(right.contains_strict(&Rect::new(50.0, 10.0, 10.0, 10.0)));
+        assert!(left.contains_strict(&Rect::new(0.0, 0.0, 49.0, 49.0)));
+    }
+
+    #[test]
+    fn contains_point_inside_and_on_edges() {
+        let rect = Rect::new(0.0, 0.0, 10.0, 10.0);
+
+        assert!(rect.contains_point(5.0, 5.0));
+        assert!(rect.contains_point(0.0, 10.0));
+        assert!(!rect.contains_point(10.5, 5.0));
+    }
+
+    // Intersection and union
+    #[test]
+    fn subtract_full_cover() {
+        let rect = Rect::new(10.0, 10.0, 10.0, 10.0);
+        assert!(rect.subtract(&Rect::new(0.0, 0.0, 50.0, 50.0)).is_empty());
+        assert!(rect.subtract(&rect).is_empty());
+    }
+
+    #[test]
+    fn subtract_without_overlap() {
+        let rect = Rect::new(10.0, 10.0, 10.0, 10.0);
+        assert_eq!(rect.subtract(&Rect::new(30.0, 0.0, 5.0, 5.0)), vec![rect]);
+        assert_eq!(rect.subtract(&Rect::new(20.0, 10.0, 5.0, 5.0)), vec![rect]);
+    }
+
+    #[test]
+    fn subtract_corner_leaves_l_shape() {
+        let rect = Rect::new(0.0, 0.0, 10.0, 10.0);
+
+        assert_eq!(
+            rect.subtract(&Rect::new(5.0, 5.0, 10.0, 10.0)),
+            vec![
+                Rect::new(0.0, 0.0, 10.0, 5.0),
+                Rect::new(0.0, 5.0, 5.0, 5.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn subtract_hole_leaves_four_pieces() {
+        let rect = Rect::new(0.0, 0.0, 10.0, 10.0);
+        let pieces = rect.subtract(&Rect::new(4.0, 4.0, 2.0, 2.0));
+
+        assert_eq!(pieces.len(), 4);
+        assert_eq!(pieces.iter().map(|piece| piece.area()).sum::<f32>(), 96.0);
+    }
+
+    #[test]
+    fn intersection_of_overlapping_rects() {
+        let a = Rect::new(0.0, 0.0, 10.0, 10.0);
+        let b = Rect::new(5.0, 2.0, 10.0, 4.0);
+
+        assert_eq!(a.intersection(&b), Some(Rect::new(5.0, 2.0, 5.0, 4.0)));
+        assert_eq!(a.intersection(&Rect::new(20.0, 0.0, 1.0, 1.0)), None);
+    }
+
+    #[test]
+    fn union_of_disjoint_rects() {
+        let a = Rect::new(0.0, 0.0, 10.0, 10.0);
+        let b = Rect::new(20.0, 5.0, 5.0, 10.0);
+
+        assert_eq!(a.union(&b), Rect::new(0.0, 0.0, 25.0, 15.0));
+    }
+
+    #[test]
+    fn hull_of_rects() {
+        let outer = Rect::new(0.0, 0.0, 10.0, 10.0);
+        let inner = Rect::new(2.0, 2.0, 3.0, 3.0);
+        let apart = Rect::new(20.0, -5.0, 5.0, 5.0);
+
+        assert_eq!(Rect::hull_of(&[]), None);
+        assert_eq!(Rect::hull_of(&[inner]), Some(inner));
+        assert_eq!(Rect::hull_of(&[inner, outer]), Some(outer));
+        assert_eq!(
+            Rect::hull_of(&[outer, apart]),
+            Some(Rect::new(0.0, -5.0, 25.0, 15.0))
+        );
+    }
+
+    // Clamping
+    #[test]
+    fn clamp_partially_outside() {
+        let bounds = Rect::new(0.0, 0.0, 100.0, 100.0);
+        let rect = Rect::new(-10.0, 90.0, 30.0, 30.0);
+
+        assert_eq!(rect.clamp(&bounds), Rect::new(0.0, 90.0, 20.0, 10.0));
+    }
+
+    #[test]
+    fn clamp_inside_is_unchanged() {
+        let bounds = Rect::new(0.0, 0.0, 100.0, 100.0);
+        let rect = Rect::new(10.0, 10.0, 30.0, 30.0);
+
+        assert_eq!(rect.clamp(&bounds), rect);
+    }
+
+    #[test]
+    fn clamp_disjoint_is_empty() {
+        let bounds = Rect::new(0.0, 0.0, 100.0, 100.0);
+        let rect = Rect::new(150.0, 10.0, 30.0, 30.0);
+
+        assert_eq!(rect.clamp(&bounds), Rect::new(100.0, 10.0, 0.0, 30.0));
+        assert_eq!(rect.clamp(&bounds).area(), 0.0);
+    }
+
+    // Overlap area
+    #[test]
+    fn overlap_area_of_disjoint_rects() {
+        let a = Rect::new(0.0, 0.0, 10.0, 10.0);
+        let b = Rect::new(20.0, 20.0, 10.0, 10.0);
+
+        assert_eq!(a.overlap_area(&b), 0.0);
+    }
+
+    #[test]
+    fn overlap_area_of_touching_rects() {
+        let a = Rect::new(0.0, 0.0, 10.0, 10.0);
+        let b = Rect::new(10.0, 0.0, 10.0, 10.0);
+
+        assert_eq!(a.overlap_area(&b), 0.0);
+    }
+
+    #[test]
+    fn overlap_area_of_partially_overlapping_rects() {
+        let a = Rect::new(0.0, 0.0, 10.0, 10.0);
+        let b = Rect::new(5.0, 8.0, 10.0, 10.0);
+
+        assert_eq!(a.overlap_area(&b), 10.0);
+        assert_eq!(b.overlap_area(&a), 10.0);
+    }
+
+    // Grid snapping
+    #[test]
+    fn snap_to_grid_expands_outward() {
+        let rect = Rect::new(3.0, 12.0, 10.0, 5.0);
+        assert_eq!(rect.snap_to_grid(10.0), Rect::new(0.0, 10.0, 20.0, 10.0));
+    }
+
+    #[test]
+    fn snap_to_grid_negative_coordinates() {
+        let rect = Rect::new(-3.0, -12.0, 2.0, 4.0);
+        assert_eq!(rect.snap_to_grid(10.0), Rect::new(-10.0, -20.0, 10.0, 20.0));
+    }
+
+    #[test]
+    fn snap_to_grid_aligned_is_unchanged() {
+        let rect = Rect::new(10.0, 20.0, 30.0, 10.0);
+        assert_eq!(rect.snap_to_grid(10.0), rect);
+    }
+
+    // IoU
+    #[test]
+    fn iou_of_identical_rects() {
+        let a = Rect::new(0.0, 0.0, 10.0, 10.0);
+        assert_eq!(a.iou(&a), 1.0);
+    }
+
+    #[test]
+    fn iou_of_disjoint_rects() {
+        let a = Rect::new(0.0, 0.0, 10.0, 10.0);
+        let b = Rect::new(20.0, 0.0, 10.0, 10.0);
+
+        assert_eq!(a.iou(&b), 0.0);
+    }
+
+    #[test]
+    fn iou_of_half_overlapping_rects() {
+        let a = Rect::new(0.0, 0.0, 10.0, 10.0);
+        let b = Rect::new(5.0, 0.0, 10.0, 10.0);
+
+        assert_eq!(a.iou(&b), 50.0 / 150.0);
+    }
+
+    #[test]
+    fn iou_of_empty_rects() {
+        let a = Rect::new(0.0, 0.0, 0.0, 0.0);
+        assert_eq!(a.iou(&a), 0.0);
+    }
+
+    // Scaling
+    #[test]
+    fn scale_xy_from_origin() {
+        let rect = Rect::new(2.0, 4.0, 6.0, 8.0);
+
+        assert_eq!(rect.scale_xy(2.0, 0.5), Rect::new(4.0, 2.0, 12.0, 4.0));
+        assert_eq!(
+            rect.scale_xy(2.0, 2.0).translate(-1.0, 1.0),
+            Rect::new(3.0, 9.0, 12.0, 16.0)
+        );
+    }
+
+    #[test]
+    fn fit_landscape_inside_portrait() {
+        let landscape = Rect::new(50.0, 50.0, 40.0, 20.0);
+        let portrait = Rect::new(0.0, 0.0, 10.0, 30.0);
+
+        assert_eq!(
+            landscape.fit_inside(&portrait),
+            Rect::new(0.0, 12.5, 10.0, 5.0)
+        );
+    }
+
+    #[test]
+    fn fit_portrait_inside_landscape() {
+        let portrait = Rect::new(0.0, 0.0, 10.0, 30.0);
+        let landscape = Rect::new(0.0, 0.0, 40.0, 15.0);
+
+        assert_eq!(
+            portrait.fit_inside(&landscape),
+            Rect::new(17.5, 0.0, 5.0, 15.0)
+        );
+        assert_eq!(
+            Rect::ZERO.fit_inside(&landscape),
+            Rect::new(20.0, 7.5, 0.0, 0.0)
+        );
+    }
+
+    // Insetting
+    #[test]
+    fn inset_per_side() {
+        let rect = Rect::new(0.0, 0.0, 10.0, 10.0);
+
+        assert_eq!(
+            rect.inset(1.0, 2.0, 3.0, 4.0),
+            Rect::new(1.0, 2.0, 6.0, 4.0)
+        );
+        assert_eq!(
+            rect.inset(-1.0, 0.0, -1.0, 0.0),
+            Rect::new(-1.0, 0.0, 12.0, 10.0)
+        );
+    }
+
+    #[test]
+    fn inset_larger_than_rect_collapses() {
+        let rect = Rect::new(0.0, 0.0, 10.0, 10.0);
+
+        assert_eq!(
+            rect.inset(8.0, 0.0, 6.0, 0.0),
+            Rect::new(6.0, 0.0, 0.0, 10.0)
+        );
+    }
+
+    // Translation
+    #[test]
+    fn translate_keeps_size() {
+        assert_eq!(
+            Rect::new(1.0, 2.0, 3.0, 4.0).translate(10.0, -2.0),
+            Rect::new(11.0, 0.0, 3.0, 4.0)
+        );
+    }
+
+    // Interpolation
+    #[test]
+    fn lerp_between_rects() {
+        let a = Rect::new(0.0, 0.0, 10.0, 10.0);
+        let b = Rect::new(10.0, 20.0, 20.0, 30.0);
+
+        assert_eq!(a.lerp(&b, 0.0), a);
+        assert_eq!(a.lerp(&b, 1.0), b);
+        assert_eq!(a.lerp(&b, 0.5), Rect::new(5.0, 10.0, 15.0, 20.0));
+        assert_eq!(a.lerp(&b, 2.0), Rect::new(20.0, 40.0, 30.0, 50.0));
+    }
+
+    // Polygon intersection
+    #[test]
+    fn intersects_triangle() {
+        let rect = Rect::new(0.0, 0.0, 10.0, 10.0);
+
+        assert!(rect.intersects_polygon(&[(5.0, 5.0), (20.0, 5.0), (20.0, 20.0)]));
+        assert!(rect.intersects_polygon(&[(-5.0, -5.0), (50.0, -5.0), (-5.0, 50.0)]));
+    }
+
+    #[test]
+    fn does_not_intersect_triangle_across_diagonal() {
+        let rect = Rect::new(0.0, 0.0, 10.0, 10.0);
+        assert!(!rect.intersects_polygon(&[(12.0, 20.0), (20.0, 12.0), (20.0, 20.0)]));
+    }
+
+    #[test]
+    fn degenerate_polygon_never_intersects() {
+        let rect = Rect::new(0.0, 0.0, 10.0, 10.0);
+
+        assert!(!rect.intersects_polygon(&[]));
+        assert!(!rect.intersects_polygon(&[(5.0, 5.0), (6.0, 6.0)]));
+    }
+
+    // Ray intersection
+    #[test]
+    fn ray_hits_rect_in_front() {
+        let rect = Rect::new(10.0, 0.0, 10.0, 10.0);
+
+        assert_eq!(rect.ray_intersection((0.0, 5.0), (1.0, 0.0)), Some(10.0));
+        assert_eq!(rect.ray_intersection((0.0, 0.0), (1.0, 1.0)), Some(10.0));
+    }
+
+    #[test]
+    fn ray_misses_rect() {
+        let rect = Rect::new(10.0, 0.0, 10.0, 10.0);
+
+        assert_eq!(rect.ray_intersection((0.0, 5.0), (-1.0, 0.0)), None);
+        assert_eq!(rect.ray_intersection((0.0, 15.0), (1.0, 0.0)), None);
+    }
+
+    #[test]
+    fn ray_starting_inside_rect() {
+        let rect = Rect::new(10.0, 0.0, 10.0, 10.0);
+        assert_eq!(rect.ray_intersection((15.0, 5.0), (0.0, -1.0)), Some(0.0));
+    }
+
+    #[test]
+    fn quadrants_split_in_child_order() {
+        let rect = Rect::new(0.0, 0.0, 10.0, 20.0);
+
+        assert_eq!(
+            rect.quadrants(),
+            [
+                Rect::new(0.0, 0.0, 5.0, 10.0),
+                Rect::new(5.0, 0.0, 5.0, 10.0),
+                Rect::new(0.0, 10.0, 5.0, 10.0),
+                Rect::new(5.0, 10.0, 5.0, 10.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn quadrant_index() {
+        let rect = Rect::new(0.0, 0.0, 10.0, 10.0);
+
+        assert_eq!(rect.quadrant_index(&Rect::new(6.0, 1.0, 1.0, 1.0)), Some(1));
+        assert_eq!(rect.quadrant_index(&Rect::new(1.0, 6.0, 1.0, 1.0)), Some(2));
+        assert_eq!(rect.quadrant_index(&Rect::new(5.0, 5.0, 0.0, 0.0)), Some(0));
+        assert_eq!(rect.quadrant_index(&Rect::new(4.0, 4.0, 2.0, 2.0)), None);
+    }
+
+    #[test]
+    fn split_horizontal() {
+        let rect = Rect::new(0.0, 10.0, 10.0, 20.0);
+
+        assert_eq!(
+            rect.split_horizontal(15.0),
+            (
+                Rect::new(0.0, 10.0, 10.0, 5.0),
+                Rect::new(0.0, 15.0, 10.0, 15.0)
+            )
+        );
+        assert_eq!(
+            rect.split_horizontal(0.0),
+            (Rect::new(0.0, 10.0, 10.0, 0.0), rect)
+        );
+    }
+
+    #[test]
+    fn split_vertical() {
+        let rect = Rect::new(0.0, 10.0, 10.0, 20.0);
+
+        assert_eq!(
+            rect.split_vertical(4.0),
+            (
+                Rect::new(0.0, 10.0, 4.0, 20.0),
+                Rect::new(4.0, 10.0, 6.0, 20.0)
+            )
+        );
+        assert_eq!(
+            rect.split_vertical(50.0),
+            (rect, Rect::new(10.0, 10.0, 0.0, 20.0))
+        );
+    }
+
+    #[test]
+    fn contains_with_margin() {
+        let zone = Rect::new(0.0, 0.0, 100.0, 100.0);
+
+        assert!(zone.contains_with_margin(&Rect::new(10.0, 10.0, 80.0, 80.0), 10.0));
+        assert!(!zone.contains_with_margin(&Rect::new(5.0, 10.0, 80.0, 80.0), 10.0));
+        assert!(zone.contains_with_margin(&Rect::new(-5.0, 0.0, 110.0, 10.0), -5.0));
+        assert!(!zone.contains_with_margin(&Rect::new(50.0, 50.0, 0.0, 0.0), 60.0));
+    }
+
+    #[test]
+    fn point_on_shared_edge() {
+        let left = Rect::new(0.0, 0.0, 10.0, 10.0);
+        let right = Rect::new(10.0, 0.0, 10.0, 10.0);
+
+        assert!(left.contains_point_inclusive(10.0, 5.0));
+        assert!(right.contains_point_inclusive(10.0, 5.0));
+        assert!(!left.contains_point_exclusive(10.0, 5.0));
+        assert!(right.contains_point_exclusive(10.0, 5.0));
+        assert!(left.contains_point(10.0, 10.0));
+        assert!(!left.contains_point_exclusive(5.0, 10.0));
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn overlaps_touching_edges() {
+        let rect = Rect::new(0.0, 0.0, 10.0, 10.0);
+        let touching = Rect::new(10.0, 5.0, 5.0, 5.0);
+        let apart = Rect::new(11.0, 5.0, 5.0, 5.0);
+
+        assert!(rect.overlaps(&touching));
+        assert!(!rect.overlaps(&apart));
+        assert_eq!(rect.overlapps(&touching), rect.overlaps(&touching));
+    }
+
+    // Keys
+    #[test]
+    fn rect_key_as_hash_map_key() {
+        let mut cache = hashbrown::HashMap::new();
+        cache.insert(RectKey(Rect::new(0.0, 0.0, 10.0, 10.0)), 42);
+
+        assert_eq!(
+            cache.get(&RectKey::from(Rect::new(0.0, 0.0, 10.0, 10.0))),
+            Some(&42)
+        );
+        assert_eq!(
+            cache.get(&RectKey::from(Rect::new(0.0, 0.0, 10.0, 11.0))),
+            None
+        );
+    }
+
+    #[test]
+    fn rect_key_compares_bits() {
+        let nan = RectKey(Rect::new(f32::NAN, 0.0, 1.0, 1.0));
+
+        assert_eq!(nan, nan);
+        assert_ne!(
+            RectKey(Rect::new(0.0, 0.0, 1.0, 1.0)),
+            RectKey(Rect::new(-0.0, 0.0, 1.0, 1.0))
+        );
+    }
+
+    // Distance
+    #[test]
+    fn closest_point() {
+        let rect = Rect::new(0.0, 0.0, 10.0, 10.0);
+
+        assert_eq!(rect.closest_point(5.0, 5.0), (5.0, 5.0));
+        assert_eq!(rect.closest_point(15.0, 5.0), (10.0, 5.0));
+        assert_eq!(rect.closest_point(-3.0, -4.0), (0.0, 0.0));
+    }
+
+    #[test]
+    fn distance_to_point() {
+        let rect = Rect::new(0.0, 0.0, 10.0, 10.0);
+
+        assert_eq!(rect.distance_to_point(5.0, 5.0), 0.0);
+        assert_eq!(rect.distance_to_point(15.0, 5.0), 5.0);
+        assert_eq!(rect.distance_to_point(-3.0, -4.0), 5.0);
+    }
+
+    #[test]
+    fn distance_between_overlapping_rects_is_zero() {
+        let a = Rect::new(0.0, 0.0, 10.0, 10.0);
+        let b = Rect::new(5.0, 5.0, 10.0, 10.0);
+
+        assert_eq!(a.distance_to_rect(&b), 0.0);
+        assert_eq!(b.distance_to_rect(&a), 0.0);
+    }
+
+    #[test]
+    fn distance_between_axis_aligned_rects() {
+        let a = Rect::new(0.0, 0.0, 10.0, 10.0);
+        let b = Rect::new(15.0, 2.0, 10.0, 10.0);
+
+        assert_eq!(a.distance_to_rect(&b), 5.0);
+        assert_eq!(b.distance_to_rect(&a), 5.0);
+    }
+
+    #[test]
+    fn distance_between_diagonal_rects() {
+        let a = Rect::new(0.0, 0.0, 10.0, 10.0);
+        let b = Rect::new(13.0, 14.0, 10.0, 10.0);
+
+        assert_eq!(a.distance_to_rect(&b), 5.0);
+        assert_eq!(b.distance_to_rect(&a), 5.0);
+    }
 }
@@ -0,0 +1,70 @@
+pub fn morton_encode(x: u32, y: u32) -> u64 {
+    spread_bits(x) | (spread_bits(y) << 1)
+}
+
+fn spread_bits(value: u32) -> u64 {
+    let mut value = value as u64;
+    value = (value | (value << 16)) & 0x0000_FFFF_0000_FFFF;
+    value = (value | (value << 8)) & 0x00FF_00FF_00FF_00FF;
+    value = (value | (value << 4)) & 0x0F0F_0F0F_0F0F_0F0F;
+    value = (value | (value << 2)) & 0x3333_3333_3333_3333;
+    value = (value | (value << 1)) & 0x5555_5555_5555_5555;
+
+    value
+}
+
+// `f32` only has these methods with `std`; without it they come from `libm`.
+
+#[cfg(feature = "std")]
+pub(crate) fn sqrt(value: f32) -> f32 {
+    value.sqrt()
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn sqrt(value: f32) -> f32 {
+    libm::sqrtf(value)
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn floor(value: f32) -> f32 {
+    value.floor()
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn floor(value: f32) -> f32 {
+    libm::floorf(value)
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn ceil(value: f32) -> f32 {
+    value.ceil()
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn ceil(value: f32) -> f32 {
+    libm::ceilf(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn morton_encode_origin() {
+        assert_eq!(morton_encode(0, 0), 0);
+    }
+
+    #[test]
+    fn morton_encode_interleaves_bits() {
+        assert_eq!(morton_encode(1, 0), 0b01);
+        assert_eq!(morton_encode(0, 1), 0b10);
+        assert_eq!(morton_encode(3, 3), 0b1111);
+        assert_eq!(morton_encode(0b101, 0b010), 0b011001);
+    }
+
+    #[test]
+    fn morton_encode_max() {
+        assert_eq!(morton_encode(u32::MAX, u32::MAX), u64::MAX);
+        assert_eq!(morton_encode(u32::MAX, 0), 0x5555_5555_5555_5555);
+    }
+}